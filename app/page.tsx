@@ -10,7 +10,7 @@ export default function Main() {
 	const router = useRouter();
 
 	useEffect(() => {
-		invoke('load_package').then(() => {
+		invoke('load_package').catch(console.error).then(() => {
 			invoke<ScanResult>('scan').then(result => {
 				if (result.selected_install_path) {
 					router.replace('/profiles');
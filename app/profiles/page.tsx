@@ -13,6 +13,7 @@ export type ProfileInfo = {
     name: string,
     icon: string,
     mods_amount: number,
+    dependencies_amount: number,
     folder: string
 };
 
@@ -58,7 +59,7 @@ function Profile({ profile, onDelete, game }: { profile: ProfileInfo, game: Game
 						</picture>
 						: <div className="bg-primary-rgb rounded-lg w-full aspect-square"></div>}
 					<a title={profile.name} className="pt-2 w-full text-medium text-start break-all overflow-ellipsis overflow-hidden">{profile.name}</a>
-					<a className="text-neutral-400">{profile.mods_amount} mods</a>
+					<a title={`${profile.dependencies_amount} dependencies`} className="text-neutral-400">{profile.mods_amount} mods</a>
 				</Button>
 				<Button disableRipple isIconOnly color={isProfileRunning ? "danger" : "success"} variant="solid"
 					className="right-6 bottom-[4.7rem] absolute opacity-0 group-hover:opacity-100 rounded-lg min-w-10 size-[45px!important]"
@@ -140,6 +141,7 @@ export default function Profiles() {
 				<div className="flex flex-row gap-4">
 					{profiles.map((profile, i) => <Profile key={i} profile={profile} game={game} onDelete={() => {
 						setProfileName(profile.name);
+						setError('');
 						onOpenDeleteProfile();
 					}} />)}
 				</div>
@@ -199,6 +201,9 @@ export default function Profiles() {
 						<ModalHeader>
 							<a>Do you want to delete profile <a className="text-blue-400">{profileName}</a>?</a>
 						</ModalHeader>
+						{error !== '' && <ModalBody>
+							<a className="text-red-400">{error}</a>
+						</ModalBody>}
 						<ModalFooter>
 							<Button onPress={onClose} className="h-9">
 								Cancel
@@ -207,7 +212,7 @@ export default function Profiles() {
 								invoke('delete_profile', { name: profileName }).then(() => {
 									getProfiles();
 									onClose();
-								});
+								}).catch(setError);
 							}}>
 								Delete
 							</Button>
@@ -1,7 +1,7 @@
 'use client'
 
 import "../globals.css"
-import { Image, Button, Checkbox, Chip, Progress, useDisclosure, ModalContent, Modal, Pagination, Select, SelectItem, ModalHeader, ModalFooter } from "@nextui-org/react";
+import { Image, Button, Checkbox, Chip, Progress, useDisclosure, ModalContent, Modal, Pagination, Select, SelectItem, ModalHeader, ModalBody, ModalFooter } from "@nextui-org/react";
 import { convertFileSrc, invoke } from "@tauri-apps/api/tauri";
 import React, { Suspense, useCallback, useEffect, useRef, useState } from "react";
 import { usePersistedState } from "@/app/_utils/state";
@@ -79,7 +79,7 @@ function _SearchTab() {
     const [types, setTypes] = usePersistedState<Record<string, number>>('search_types', { Mods: 0, Modpacks: 0 });
     const [categories, setCategories] = usePersistedState<Record<string, boolean>>('search_categories', {});
     const [scroll, setScroll] = usePersistedState('search_scroll_position', 0);
-    const [sortOrder, setSortOrder] = usePersistedState('search_sort_order', 'rating');
+    const [sortOrder, setSortOrder] = usePersistedState('search_sort_order', 'relevance');
     const [page, setPage] = usePersistedState('search_page', 0);
 
     const { isOpen: isDownloadProgressOpen, onOpen: onOpenDownloadProgress, onClose: onCloseDownloadProgress } = useDisclosure();
@@ -87,6 +87,7 @@ function _SearchTab() {
 
     const [loading, setLoading] = useState(false);
     const [downloadProgress, setDownloadProgress] = useState<DownloadProgress>();
+    const [downloadError, setDownloadError] = useState('');
 
     const ref = useRef<HTMLDivElement>(null);
 
@@ -120,7 +121,7 @@ function _SearchTab() {
             invoke('download_mod', {
                 profileName: profile.name,
                 versionName
-            }).then(getProfiles);
+            }).then(getProfiles).catch(e => setDownloadError(String(e)));
         } else {
             onOpenProfileSelection();
         }
@@ -277,6 +278,7 @@ function _SearchTab() {
                                     label: "whitespace-nowrap",
                                     trigger: "min-h-0 h-9 rounded-lg bg-primary-rgb",
                                 }}>
+                                <SelectItem key="relevance" value="relevance">Relevance</SelectItem>
                                 <SelectItem key="rating" value="rating">Rating</SelectItem>
                                 <SelectItem key="created" value="created">Newest</SelectItem>
                                 <SelectItem key="updated" value="updated">Updated</SelectItem>
@@ -348,6 +350,27 @@ function _SearchTab() {
                     </div>
                 )}</ModalContent>
             </Modal>
+            <Modal hideCloseButton
+                isOpen={downloadError !== ''}
+                onClose={() => setDownloadError('')}
+                classNames={{
+                    header: "border-b-[1px] bg-background-rgb p-4 border-[#353535]",
+                    footer: "border-t-[1px] bg-background-rgb p-4 border-[#353535]"
+                }}>
+                <ModalContent>{(onClose) => (<>
+                    <ModalHeader>
+                        <a>Failed to install</a>
+                    </ModalHeader>
+                    <ModalBody className="bg-background-rgb p-4">
+                        <a className="text-red-400 whitespace-pre-wrap break-all">{downloadError}</a>
+                    </ModalBody>
+                    <ModalFooter>
+                        <Button disableRipple color="default" className="flex items-center px-4 rounded-lg h-8" onPress={onClose}>
+                            Close
+                        </Button>
+                    </ModalFooter>
+                </>)}</ModalContent>
+            </Modal>
             <Modal hideCloseButton
                 isOpen={isProfileSelectionOpen}
                 onClose={onCloseProfileSelection}
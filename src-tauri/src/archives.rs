@@ -0,0 +1,432 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fs::{File, OpenOptions},
+    io::{Read, Write},
+    path::Path,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex as StdMutex,
+    },
+};
+
+use chrono::{DateTime, Utc};
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tokio::sync::Mutex;
+
+use crate::{profiles, thunderstore::{Origin, Version}, userdata};
+
+static INDEX: Lazy<Mutex<ArchiveIndex>> = Lazy::new(|| Mutex::new(read_index()));
+/// Bumped whenever versions are added to or removed from the cache
+static GENERATION: AtomicU64 = AtomicU64::new(0);
+/// Hashes of the archives that were checked against their name since the app started
+static VERIFIED: Lazy<StdMutex<HashSet<String>>> = Lazy::new(|| StdMutex::new(HashSet::new()));
+/// Versions whose archives running jobs are about to use, and how many jobs are
+static PINNED: Lazy<StdMutex<HashMap<String, usize>>> = Lazy::new(|| StdMutex::new(HashMap::new()));
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct ArchiveEntry {
+    /// SHA-256 of the archive, which is also its file name
+    pub hash: String,
+    pub size: u64,
+    pub last_used: DateTime<Utc>,
+}
+
+/// Archives in the cache by the version they were downloaded for. Versions with the same
+/// content share a single file.
+#[derive(Serialize, Deserialize, Default)]
+struct ArchiveIndex {
+    versions: HashMap<String, ArchiveEntry>,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct ArchiveCacheInfo {
+    /// Size of every archive in the cache, in bytes
+    pub size: u64,
+    pub archives: usize,
+    pub versions: usize,
+    pub limit: Option<u64>,
+}
+
+/// Keeps the archives of some versions in the cache until it's dropped
+pub struct ArchivePins {
+    versions: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Default)]
+pub struct PruneResult {
+    /// Versions whose archive was removed
+    pub removed: Vec<String>,
+    pub freed: u64,
+}
+
+pub fn get_archives_dir() -> String {
+    format!("{}\\archives", userdata::get_cache_dir())
+}
+
+fn get_index_path() -> String {
+    format!("{}\\index.json", get_archives_dir())
+}
+
+fn get_archive_file(hash: &str) -> String {
+    format!("{}\\{hash}.zip", get_archives_dir())
+}
+
+/// Where the archive of `version` is downloaded to before it's added to the cache. Partial
+/// downloads are kept next to it, so they can be resumed.
+pub fn get_download_path(version: &Version) -> String {
+    format!("{}\\downloads\\{}.zip", get_archives_dir(), version.full_name)
+}
+
+fn read_index() -> ArchiveIndex {
+    let mut data = String::new();
+    File::open(get_index_path())
+        .and_then(|mut f| f.read_to_string(&mut data))
+        .ok()
+        .and_then(|_| serde_json::from_str(&data).ok())
+        .unwrap_or_default()
+}
+
+fn write_index(index: &ArchiveIndex) -> Result<(), String> {
+    // Write to a temporary file first so an interrupted write never loses the index
+    let index_path = get_index_path();
+    let temp_file = format!("{index_path}.tmp");
+    OpenOptions::new()
+        .create(true)
+        .truncate(true)
+        .write(true)
+        .open(&temp_file)
+        .and_then(|mut f| f.write_all(serde_json::to_string(index).unwrap().as_bytes()))
+        .and_then(|_| std::fs::rename(&temp_file, &index_path))
+        .map_err(|e| format!("Failed to save the archive index: {e}"))
+}
+
+fn hash_file(path: &str) -> std::io::Result<String> {
+    let mut file = File::open(path)?;
+    let mut hasher = Sha256::new();
+    let mut buf = vec![0; 64 * 1024];
+
+    loop {
+        let read = file.read(&mut buf)?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buf[..read]);
+    }
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+/// Path of the cached archive of `version`, if there's one and it's whole. Each archive's
+/// content is checked against its hash the first time it's used. Private versions are never
+/// cached, their archives are used where they are.
+pub async fn find(version: &Version) -> Option<String> {
+    if version.origin == Origin::Private {
+        return None;
+    }
+    let entry = INDEX.lock().await.versions.get(&version.full_name)?.clone();
+    let path = get_archive_file(&entry.hash);
+
+    match std::fs::metadata(&path) {
+        Ok(metadata) if metadata.len() == entry.size => {}
+        _ => return None,
+    }
+    if is_verified(&path, &entry.hash).await {
+        Some(path)
+    } else {
+        println!("Cached archive of {} is corrupted", version.full_name);
+        None
+    }
+}
+
+/// Whether the archive at `path` has the content `hash` says, hashing it if it wasn't yet
+async fn is_verified(path: &str, hash: &str) -> bool {
+    if VERIFIED.lock().unwrap().contains(hash) {
+        return true;
+    }
+
+    let file = path.to_owned();
+    let verified = tauri::async_runtime::spawn_blocking(move || hash_file(&file))
+        .await
+        .map_or(false, |h| h.map_or(false, |h| h == hash));
+    if verified {
+        VERIFIED.lock().unwrap().insert(hash.to_owned());
+    }
+    verified
+}
+
+/// Changes whenever the set of cached versions does
+pub fn get_generation() -> u64 {
+    GENERATION.load(Ordering::Relaxed)
+}
+
+/// Versions with an archive in the cache
+pub async fn get_cached_versions() -> HashSet<String> {
+    INDEX.lock().await.versions.keys().cloned().collect()
+}
+
+/// Records that the cached archive of `version` was used, so it's the last to be evicted
+pub async fn mark_used(version: &Version) -> Result<(), String> {
+    let mut index = INDEX.lock().await;
+    if let Some(entry) = index.versions.get_mut(&version.full_name) {
+        entry.last_used = Utc::now();
+        write_index(&index)?;
+    }
+    Ok(())
+}
+
+/// Moves the archive of `version` downloaded to `path` into the cache. Returns where it ended up.
+pub async fn store(version: &Version, path: &str) -> Result<String, String> {
+    let file = path.to_owned();
+    let hash = tauri::async_runtime::spawn_blocking(move || hash_file(&file))
+        .await
+        .map_err(|e| e.to_string())?
+        .map_err(|e| format!("Failed to hash {}: {e}", version.full_name))?;
+    let size = std::fs::metadata(path).map_err(|e| e.to_string())?.len();
+    let archive_path = get_archive_file(&hash);
+
+    if Path::new(&archive_path).exists() && is_verified(&archive_path, &hash).await {
+        // Same content as an archive we already have
+        let _ = std::fs::remove_file(path);
+    } else {
+        std::fs::rename(path, &archive_path).map_err(|e| format!("Failed to cache {}: {e}", version.full_name))?;
+        VERIFIED.lock().unwrap().insert(hash.clone());
+    }
+
+    let mut index = INDEX.lock().await;
+    index.versions.insert(version.full_name.clone(), ArchiveEntry { hash, size, last_used: Utc::now() });
+    write_index(&index)?;
+    GENERATION.fetch_add(1, Ordering::Relaxed);
+    Ok(archive_path)
+}
+
+/// Keeps the archives of `versions` from being evicted while a job uses them. The cache isn't
+/// pruned while any are pinned.
+pub fn pin(versions: &[Version]) -> ArchivePins {
+    let versions: Vec<String> = versions.iter().map(|v| v.full_name.clone()).collect();
+    let mut pinned = PINNED.lock().unwrap();
+    for full_name in &versions {
+        *pinned.entry(full_name.clone()).or_default() += 1;
+    }
+    ArchivePins { versions }
+}
+
+impl Drop for ArchivePins {
+    fn drop(&mut self) {
+        let mut pinned = PINNED.lock().unwrap();
+        for full_name in &self.versions {
+            if let Some(count) = pinned.get_mut(full_name) {
+                *count -= 1;
+                if *count == 0 {
+                    pinned.remove(full_name);
+                }
+            }
+        }
+    }
+}
+
+/// Versions installed in any profile
+async fn get_referenced_versions() -> HashSet<String> {
+    let mut referenced = HashSet::new();
+    for profile in profiles::get_profiles().await {
+        for _mod in profiles::read_installed_mods(&profile.name).await {
+            referenced.insert(format!("{}-{}", _mod.full_name, _mod.version_number));
+        }
+    }
+    referenced
+}
+
+/// Drops `versions` from the index, deleting the archives nothing else points to
+fn remove_versions(index: &mut ArchiveIndex, versions: &[String]) -> Result<PruneResult, String> {
+    let mut result = PruneResult::default();
+    let mut hashes = HashSet::new();
+
+    for full_name in versions {
+        if let Some(entry) = index.versions.remove(full_name) {
+            hashes.insert(entry.hash);
+            result.removed.push(full_name.clone());
+        }
+    }
+
+    for hash in hashes {
+        if index.versions.values().any(|e| e.hash == hash) {
+            continue;
+        }
+        let path = get_archive_file(&hash);
+        if let Ok(metadata) = std::fs::metadata(&path) {
+            std::fs::remove_file(&path).map_err(|e| format!("Failed to remove {path}: {e}"))?;
+            result.freed += metadata.len();
+        }
+    }
+
+    write_index(index)?;
+    GENERATION.fetch_add(1, Ordering::Relaxed);
+    Ok(result)
+}
+
+fn get_cache_size(index: &ArchiveIndex) -> u64 {
+    let mut seen = HashSet::new();
+    index
+        .versions
+        .values()
+        .filter(|e| seen.insert(&e.hash))
+        .map(|e| e.size)
+        .sum()
+}
+
+/// Picks the versions to evict for the cache to fit in `limit`. Archives no profile uses go
+/// first, then the ones that were used the longest ago. Pinned versions are never picked.
+fn select_evictions(index: &ArchiveIndex, referenced: &HashSet<String>, pinned: &HashSet<String>, limit: u64) -> Vec<String> {
+    let mut candidates: Vec<(&String, &ArchiveEntry)> = index
+        .versions
+        .iter()
+        .filter(|(full_name, _)| !pinned.contains(*full_name))
+        .collect();
+    candidates.sort_by_key(|(full_name, entry)| (referenced.contains(*full_name), entry.last_used));
+
+    let mut size = get_cache_size(index);
+    let mut evicted = vec![];
+    let mut evicted_hashes = HashSet::new();
+    for (full_name, entry) in candidates {
+        if size <= limit {
+            break;
+        }
+        evicted.push(full_name.clone());
+        // Shared archives only free space once every version using them is gone
+        let shared = index
+            .versions
+            .iter()
+            .any(|(n, e)| e.hash == entry.hash && !evicted.contains(n));
+        if !shared && evicted_hashes.insert(entry.hash.clone()) {
+            size -= entry.size;
+        }
+    }
+    evicted
+}
+
+/// Evicts archives until the cache fits in the configured limit, leaving the ones running jobs
+/// are about to use
+pub async fn enforce_limit() -> Result<PruneResult, String> {
+    let Some(limit) = userdata::get_settings().await.archive_cache_limit else {
+        return Ok(PruneResult::default());
+    };
+    let referenced = get_referenced_versions().await;
+
+    let mut index = INDEX.lock().await;
+    let pinned: HashSet<String> = PINNED.lock().unwrap().keys().cloned().collect();
+    let evicted = select_evictions(&index, &referenced, &pinned, limit);
+    remove_versions(&mut index, &evicted)
+}
+
+#[tauri::command]
+pub async fn get_archive_cache_info() -> ArchiveCacheInfo {
+    let limit = userdata::get_settings().await.archive_cache_limit;
+    let index = INDEX.lock().await;
+
+    ArchiveCacheInfo {
+        size: get_cache_size(&index),
+        archives: index.versions.values().map(|e| &e.hash).collect::<HashSet<_>>().len(),
+        versions: index.versions.len(),
+        limit,
+    }
+}
+
+/// Removes the archives of versions that aren't installed in any profile
+#[tauri::command]
+pub async fn prune_archive_cache() -> Result<PruneResult, String> {
+    // Installs in progress have partial downloads and archives they're about to extract
+    if !PINNED.lock().unwrap().is_empty() {
+        return Err(String::from("Can't prune the cache while mods are being installed"));
+    }
+    let referenced = get_referenced_versions().await;
+
+    let mut index = INDEX.lock().await;
+    let unused: Vec<String> = index
+        .versions
+        .keys()
+        .filter(|v| !referenced.contains(*v))
+        .cloned()
+        .collect();
+    let result = remove_versions(&mut index, &unused)?;
+
+    // Downloads that never finished aren't worth keeping either
+    let _ = std::fs::remove_dir_all(format!("{}\\downloads", get_archives_dir()));
+    let _ = std::fs::create_dir_all(format!("{}\\downloads", get_archives_dir()));
+
+    Ok(result)
+}
+
+/// Sets the most the archive cache may take up in bytes, `None` for no limit
+#[tauri::command]
+pub async fn set_archive_cache_limit(limit: Option<u64>) -> Result<PruneResult, String> {
+    userdata::get_settings().await.archive_cache_limit = limit;
+    userdata::save_data().await;
+    enforce_limit().await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn index(entries: &[(&str, &str, u64, i64)]) -> ArchiveIndex {
+        ArchiveIndex {
+            versions: entries
+                .iter()
+                .map(|(full_name, hash, size, used)| {
+                    let entry = ArchiveEntry {
+                        hash: hash.to_string(),
+                        size: *size,
+                        last_used: Utc.timestamp_opt(*used, 0).unwrap(),
+                    };
+                    (full_name.to_string(), entry)
+                })
+                .collect(),
+        }
+    }
+
+    fn set(names: &[&str]) -> HashSet<String> {
+        names.iter().map(|n| n.to_string()).collect()
+    }
+
+    #[test]
+    fn shared_archives_are_counted_once() {
+        let index = index(&[("A-a-1.0.0", "x", 10, 0), ("B-b-1.0.0", "x", 10, 0), ("C-c-1.0.0", "y", 5, 0)]);
+        assert_eq!(get_cache_size(&index), 15);
+    }
+
+    #[test]
+    fn evicts_unreferenced_then_oldest() {
+        let index = index(&[("A-a-1.0.0", "a", 10, 1), ("B-b-1.0.0", "b", 10, 2), ("C-c-1.0.0", "c", 10, 3)]);
+        let evicted = select_evictions(&index, &set(&["A-a-1.0.0"]), &set(&[]), 15);
+        assert_eq!(evicted, vec!["B-b-1.0.0", "C-c-1.0.0"]);
+
+        let evicted = select_evictions(&index, &set(&[]), &set(&[]), 20);
+        assert_eq!(evicted, vec!["A-a-1.0.0"]);
+    }
+
+    #[test]
+    fn nothing_is_evicted_under_the_limit() {
+        let index = index(&[("A-a-1.0.0", "a", 10, 1)]);
+        assert!(select_evictions(&index, &set(&[]), &set(&[]), 10).is_empty());
+    }
+
+    #[test]
+    fn pinned_versions_are_kept() {
+        let index = index(&[("A-a-1.0.0", "a", 10, 1), ("B-b-1.0.0", "b", 10, 2)]);
+        let evicted = select_evictions(&index, &set(&[]), &set(&["A-a-1.0.0"]), 0);
+        assert_eq!(evicted, vec!["B-b-1.0.0"]);
+    }
+
+    #[test]
+    fn shared_archives_free_space_with_their_last_version() {
+        let index = index(&[("A-a-1.0.0", "x", 10, 1), ("B-b-1.0.0", "x", 10, 2), ("C-c-1.0.0", "c", 10, 3)]);
+        // Evicting A alone frees nothing, B has to go too
+        let evicted = select_evictions(&index, &set(&[]), &set(&[]), 10);
+        assert_eq!(evicted, vec!["A-a-1.0.0", "B-b-1.0.0"]);
+
+        // A pinned version keeps the archive it shares
+        let evicted = select_evictions(&index, &set(&[]), &set(&["B-b-1.0.0"]), 10);
+        assert_eq!(evicted, vec!["A-a-1.0.0", "C-c-1.0.0"]);
+    }
+}
@@ -0,0 +1,299 @@
+use std::{
+    future::Future,
+    path::Path,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use futures_util::future::{self, Either};
+use serde::{Deserialize, Serialize};
+use tauri::Manager;
+use tokio::sync::Semaphore;
+
+use crate::{archives, jobs::{self, JobControl}, sources, thunderstore::{Origin, Version}, userdata};
+
+const DEFAULT_CONCURRENT_DOWNLOADS: usize = 4;
+/// Byte and extraction progress is emitted at most this often, state changes always are
+const EMIT_INTERVAL: Duration = Duration::from_millis(100);
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ItemState {
+    Queued,
+    Downloading,
+    Paused,
+    Downloaded,
+    Extracting,
+    Done,
+    Failed,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct DownloadItem {
+    pub name: String,
+    pub full_name: String,
+    pub state: ItemState,
+    pub downloaded_bytes: u64,
+    pub total_bytes: u64,
+    pub extract_progress: f32,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct DownloadProgress {
+    /// Job the mods are installed by
+    pub job: u64,
+    pub current_mod: String,
+    pub total_progress: f32,
+    pub extract_progress: f32,
+    pub items: Vec<DownloadItem>,
+    pub downloaded_bytes: u64,
+    pub total_bytes: u64,
+}
+
+struct TrackerState {
+    progress: DownloadProgress,
+    last_emit: Option<Instant>,
+}
+
+/// Progress of a batch of mods being installed, emitted as `download_progress`
+pub struct DownloadTracker {
+    state: Mutex<TrackerState>,
+}
+
+fn emit_progress(progress: &DownloadProgress) {
+    if let Some(app) = jobs::get_app() {
+        let _ = app.emit_all("download_progress", progress.clone());
+    }
+}
+
+impl DownloadItem {
+    /// How much of the item is done, downloading and extracting take half each
+    fn completion(&self) -> f32 {
+        match self.state {
+            ItemState::Queued | ItemState::Failed => 0.0,
+            ItemState::Downloading | ItemState::Paused => 0.5 * self.downloaded_bytes as f32 / self.total_bytes.max(1) as f32,
+            ItemState::Downloaded => 0.5,
+            ItemState::Extracting => 0.5 + 0.5 * self.extract_progress / 100.0,
+            ItemState::Done => 1.0,
+        }
+    }
+}
+
+impl DownloadTracker {
+    pub fn new(job: u64, versions: &[Version]) -> DownloadTracker {
+        let items: Vec<DownloadItem> = versions
+            .iter()
+            .map(|v| DownloadItem {
+                name: v.name.clone(),
+                full_name: v.full_name.clone(),
+                state: ItemState::Queued,
+                downloaded_bytes: 0,
+                total_bytes: v.file_size.max(0) as u64,
+                extract_progress: 0.0,
+            })
+            .collect();
+
+        DownloadTracker {
+            state: Mutex::new(TrackerState {
+                progress: DownloadProgress {
+                    job,
+                    current_mod: String::new(),
+                    total_progress: 0.0,
+                    extract_progress: 0.0,
+                    downloaded_bytes: 0,
+                    total_bytes: items.iter().map(|i| i.total_bytes).sum(),
+                    items,
+                },
+                last_emit: None,
+            }),
+        }
+    }
+
+    fn update(&self, force: bool, f: impl FnOnce(&mut DownloadProgress)) {
+        let mut state = self.state.lock().unwrap();
+        let progress = &mut state.progress;
+        f(progress);
+
+        progress.downloaded_bytes = progress.items.iter().map(|i| i.downloaded_bytes).sum();
+        if !progress.items.is_empty() {
+            let completion: f32 = progress.items.iter().map(|i| i.completion()).sum();
+            progress.total_progress = completion / progress.items.len() as f32 * 100.0;
+        }
+
+        let now = Instant::now();
+        if force || state.last_emit.map_or(true, |t| now - t >= EMIT_INTERVAL) {
+            state.last_emit = Some(now);
+            emit_progress(&state.progress);
+        }
+    }
+
+    pub fn set_state(&self, item: usize, item_state: ItemState) {
+        self.update(true, |p| {
+            let i = &mut p.items[item];
+            i.state = item_state;
+            match item_state {
+                ItemState::Downloading => p.current_mod = format!("Downloading {}...", i.name),
+                ItemState::Downloaded => i.downloaded_bytes = i.total_bytes,
+                ItemState::Extracting => {
+                    p.current_mod = format!("Extracting {}...", i.name);
+                    p.extract_progress = 0.0;
+                }
+                ItemState::Done => i.extract_progress = 100.0,
+                _ => {}
+            }
+        });
+    }
+
+    pub fn set_downloaded(&self, item: usize, bytes: u64) {
+        self.update(false, |p| {
+            let i = &mut p.items[item];
+            i.downloaded_bytes = bytes;
+            // Sizes from the index can be off, never report more than everything
+            i.total_bytes = i.total_bytes.max(bytes);
+            p.total_bytes = p.items.iter().map(|i| i.total_bytes).sum();
+        });
+    }
+
+    pub fn set_extract_progress(&self, item: usize, extract_progress: f32) {
+        self.update(false, |p| {
+            p.items[item].extract_progress = extract_progress;
+            p.extract_progress = extract_progress;
+        });
+    }
+
+    /// Reports a step that isn't about a single mod
+    pub fn message(&self, message: &str, total_progress: f32) {
+        let mut state = self.state.lock().unwrap();
+        state.progress.current_mod = message.to_owned();
+        state.progress.total_progress = total_progress;
+        state.progress.extract_progress = 100.0;
+        state.last_emit = Some(Instant::now());
+        emit_progress(&state.progress);
+    }
+}
+
+#[tauri::command]
+pub async fn get_max_concurrent_downloads() -> usize {
+    userdata::get_settings()
+        .await
+        .max_concurrent_downloads
+        .unwrap_or(DEFAULT_CONCURRENT_DOWNLOADS)
+}
+
+#[tauri::command]
+pub async fn set_max_concurrent_downloads(amount: usize) {
+    userdata::get_settings().await.max_concurrent_downloads = Some(amount.max(1));
+    userdata::save_data().await;
+}
+
+/// Fetches the archives of `versions`, several at a time. Each archive goes through `prepare`
+/// as soon as it's there, and what that returns is handed to `on_downloaded` in the order of
+/// `versions`, once every one before it is done, while the rest keep downloading. Downloads
+/// stop when `control` pauses or cancels the job.
+pub async fn download_all<P, PFut, F, FFut>(tracker: Arc<DownloadTracker>, control: Arc<JobControl>, versions: &[Version], prepare: P, mut on_downloaded: F) -> Result<(), String>
+where
+    P: Fn(usize, Version, String) -> PFut + Clone + Send + 'static,
+    PFut: Future<Output = Result<String, String>> + Send + 'static,
+    F: FnMut(usize, Version, String) -> FFut,
+    FFut: Future<Output = Result<(), String>>,
+{
+    let semaphore = Arc::new(Semaphore::new(get_max_concurrent_downloads().await.max(1)));
+
+    let handles: Vec<_> = versions
+        .iter()
+        .cloned()
+        .enumerate()
+        .map(|(i, version)| {
+            let semaphore = semaphore.clone();
+            let tracker = tracker.clone();
+            let control = control.clone();
+            let prepare = prepare.clone();
+
+            tauri::async_runtime::spawn(async move {
+                let archive_path = fetch(i, &version, &semaphore, &tracker, &control).await?;
+                prepare(i, version, archive_path).await
+            })
+        })
+        .collect();
+
+    let mut handles = handles.into_iter().enumerate();
+    while let Some((i, handle)) = handles.next() {
+        let result = match handle.await {
+            // Extraction can't be paused, so wait before starting it
+            Ok(Ok(archive_path)) => match control.checkpoint().await {
+                Ok(()) => {
+                    tracker.set_state(i, ItemState::Extracting);
+                    on_downloaded(i, versions[i].clone(), archive_path).await
+                }
+                Err(e) => Err(e),
+            },
+            Ok(Err(e)) => Err(e),
+            Err(e) => Err(e.to_string()),
+        };
+
+        if let Err(e) = result {
+            tracker.set_state(i, ItemState::Failed);
+            for (_, handle) in handles {
+                handle.abort();
+            }
+            return Err(e);
+        }
+        tracker.set_state(i, ItemState::Done);
+    }
+
+    Ok(())
+}
+
+/// Gets the archive of `version` from where it's kept, or downloads it into the cache
+async fn fetch(i: usize, version: &Version, semaphore: &Semaphore, tracker: &DownloadTracker, control: &JobControl) -> Result<String, String> {
+    control.checkpoint().await?;
+
+    // Private archives are already on disk, and may be rebuilt under the same version
+    if version.origin == Origin::Private {
+        if !Path::new(&version.download_url).exists() {
+            return Err(format!("{} is missing", version.download_url));
+        }
+        tracker.set_state(i, ItemState::Downloaded);
+        return Ok(version.download_url.clone());
+    }
+
+    if let Some(archive_path) = archives::find(version).await {
+        archives::mark_used(version).await?;
+        tracker.set_state(i, ItemState::Downloaded);
+        return Ok(archive_path);
+    }
+
+    let _permit = semaphore.acquire().await.map_err(|e| e.to_string())?;
+    println!("Downloading {}...", version.full_name);
+
+    let download_path = archives::get_download_path(version);
+    if let Some(parent) = Path::new(&download_path).parent() {
+        std::fs::create_dir_all(parent).map_err(|e| e.to_string())?;
+    }
+
+    // A paused download is dropped, and picks up from the partial file on resume
+    let source = sources::get_version_source(version).await;
+    let on_progress = |bytes| tracker.set_downloaded(i, bytes);
+    let fetched = loop {
+        control.checkpoint().await?;
+        tracker.set_state(i, ItemState::Downloading);
+
+        let fetch = Box::pin(source.fetch_archive(version, &download_path, &on_progress));
+        match future::select(fetch, Box::pin(control.interrupted())).await {
+            Either::Left((result, _)) => break result,
+            Either::Right(_) => tracker.set_state(i, ItemState::Paused),
+        }
+    };
+
+    match fetched {
+        Ok(()) => {
+            let archive_path = archives::store(version, &download_path).await?;
+            tracker.set_state(i, ItemState::Downloaded);
+            Ok(archive_path)
+        }
+        Err(e) => {
+            tracker.set_state(i, ItemState::Failed);
+            Err(format!("Failed to download {}: {e}", version.full_name))
+        }
+    }
+}
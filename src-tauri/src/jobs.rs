@@ -0,0 +1,379 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fs::{File, OpenOptions},
+    io::{Read, Write},
+    path::Path,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex as StdMutex,
+    },
+};
+
+use chrono::{DateTime, Utc};
+use once_cell::sync::{Lazy, OnceCell};
+use serde::{Deserialize, Serialize};
+use tauri::{AppHandle, Manager};
+use tokio::sync::{watch, Mutex, OwnedMutexGuard};
+
+use crate::{profiles, snapshot::Snapshot, userdata};
+
+pub const CANCELLED: &str = "Cancelled";
+
+static APP: OnceCell<AppHandle> = OnceCell::new();
+static JOB_COUNTER: AtomicU64 = AtomicU64::new(1);
+static QUEUE: Lazy<Mutex<JobQueue>> = Lazy::new(|| Mutex::new(JobQueue::default()));
+/// Held while a profile's mods are being changed, by a job or by a command
+static PROFILE_LOCKS: Lazy<StdMutex<HashMap<String, Arc<Mutex<()>>>>> = Lazy::new(|| StdMutex::new(HashMap::new()));
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum JobState {
+    Queued,
+    Running,
+    Paused,
+    Cancelling,
+    Done,
+    Failed,
+    Cancelled,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Job {
+    pub id: u64,
+    pub profile: String,
+    /// Versions that were asked for, not counting dependencies
+    pub versions: Vec<String>,
+    /// Whether the versions are marked as installed by the user
+    pub requested: bool,
+    pub state: JobState,
+    pub created: DateTime<Utc>,
+    pub error: Option<String>,
+    /// Snapshot of the profile while the job runs, to roll it back after a crash
+    pub snapshot: Option<String>,
+}
+
+/// What a running job was told to do, checked by the job at points where it can stop safely
+#[derive(Clone, Copy, PartialEq)]
+enum Signal {
+    Run,
+    Pause,
+    Cancel,
+}
+
+pub struct JobControl {
+    signal: watch::Sender<Signal>,
+    result: watch::Sender<Option<Result<Vec<String>, String>>>,
+}
+
+/// Jobs of every profile, in the order they were added. A profile runs one job at a time.
+#[derive(Default)]
+struct JobQueue {
+    jobs: Vec<(Job, Arc<JobControl>)>,
+    /// Profiles with a worker running their jobs
+    workers: HashSet<String>,
+}
+
+impl JobState {
+    fn is_finished(&self) -> bool {
+        matches!(self, JobState::Done | JobState::Failed | JobState::Cancelled)
+    }
+}
+
+impl JobControl {
+    fn new() -> JobControl {
+        JobControl {
+            signal: watch::channel(Signal::Run).0,
+            result: watch::channel(None).0,
+        }
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        *self.signal.borrow() == Signal::Cancel
+    }
+
+    /// Fails if the job was cancelled, for work that can't wait out a pause
+    pub fn check(&self) -> Result<(), String> {
+        if self.is_cancelled() {
+            Err(String::from(CANCELLED))
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Waits while the job is paused, and fails if it was cancelled
+    pub async fn checkpoint(&self) -> Result<(), String> {
+        let mut receiver = self.signal.subscribe();
+        let _ = receiver.wait_for(|s| *s != Signal::Pause).await;
+        self.check()
+    }
+
+    /// Resolves once the job is paused or cancelled, so whatever it's racing can be dropped
+    pub async fn interrupted(&self) {
+        let mut receiver = self.signal.subscribe();
+        let _ = receiver.wait_for(|s| *s != Signal::Run).await;
+    }
+}
+
+impl JobQueue {
+    fn get_mut(&mut self, id: u64) -> Result<&mut (Job, Arc<JobControl>), String> {
+        self.jobs
+            .iter_mut()
+            .find(|(j, _)| j.id == id)
+            .ok_or_else(|| format!("There's no job {id}"))
+    }
+
+    /// Writes the jobs to disk and lets the frontend know they changed
+    fn save(&self) {
+        let jobs: Vec<&Job> = self.jobs.iter().map(|(job, _)| job).collect();
+        let buf = serde_json::to_string(&jobs).unwrap();
+
+        let saved = OpenOptions::new()
+            .create(true)
+            .truncate(true)
+            .write(true)
+            .open(get_jobs_path())
+            .and_then(|mut f| f.write_all(buf.as_bytes()));
+        if let Err(e) = saved {
+            println!("Failed to save jobs: {e}");
+        }
+
+        if let Some(app) = APP.get() {
+            let _ = app.emit_all("jobs_changed", jobs);
+        }
+    }
+
+    /// Starts running the jobs of `profile`, unless that's already happening
+    fn start_worker(&mut self, profile: &str) {
+        if self.workers.insert(profile.to_owned()) {
+            tauri::async_runtime::spawn(run_worker(profile.to_owned()));
+        }
+    }
+}
+
+fn get_jobs_path() -> String {
+    format!("{}\\jobs.json", userdata::get_app_dir())
+}
+
+pub fn get_app() -> Option<&'static AppHandle> {
+    APP.get()
+}
+
+/// Waits until nothing else is changing the mods of `profile`, and keeps it that way until the
+/// guard is dropped. Running jobs hold it, so changes made outside of the queue wait for them.
+pub async fn lock_profile(profile: &str) -> OwnedMutexGuard<()> {
+    let lock = PROFILE_LOCKS.lock().unwrap().entry(profile.to_owned()).or_default().clone();
+    lock.lock_owned().await
+}
+
+/// Runs the jobs of a profile one after another, until there are none left
+async fn run_worker(profile: String) {
+    loop {
+        let next = {
+            let mut queue = QUEUE.lock().await;
+            let next = queue
+                .jobs
+                .iter_mut()
+                .find(|(j, _)| j.profile == profile && j.state == JobState::Queued)
+                .map(|(job, control)| {
+                    job.state = JobState::Running;
+                    (job.clone(), control.clone())
+                });
+
+            match next {
+                Some(next) => {
+                    queue.save();
+                    next
+                }
+                None => {
+                    queue.workers.remove(&profile);
+                    return;
+                }
+            }
+        };
+
+        let (job, control) = next;
+        let _lock = lock_profile(&job.profile).await;
+        let result = profiles::run_install(job.id, control, &job.profile, &job.versions, job.requested).await;
+        finish(job.id, result).await;
+    }
+}
+
+/// Queues installing `versions` into `profile`, after the jobs already queued for it
+pub async fn enqueue(profile: &str, versions: &[String], requested: bool) -> u64 {
+    let job = Job {
+        id: JOB_COUNTER.fetch_add(1, Ordering::Relaxed),
+        profile: profile.to_owned(),
+        versions: versions.to_vec(),
+        requested,
+        state: JobState::Queued,
+        created: Utc::now(),
+        error: None,
+        snapshot: None,
+    };
+
+    let id = job.id;
+    let mut queue = QUEUE.lock().await;
+    queue.jobs.push((job, Arc::new(JobControl::new())));
+    queue.save();
+    queue.start_worker(profile);
+    id
+}
+
+/// Waits for a job to finish. Returns the versions it installed.
+pub async fn wait(id: u64) -> Result<Vec<String>, String> {
+    let mut receiver = QUEUE.lock().await.get_mut(id)?.1.result.subscribe();
+    let result = receiver.wait_for(|r| r.is_some()).await.map_err(|e| e.to_string())?;
+    result.clone().unwrap()
+}
+
+/// Records the snapshot a job is working with, or that it's done with it
+pub async fn set_snapshot(id: u64, snapshot: Option<String>) {
+    let mut queue = QUEUE.lock().await;
+    if let Ok((job, _)) = queue.get_mut(id) {
+        job.snapshot = snapshot;
+        queue.save();
+    }
+}
+
+async fn finish(id: u64, result: Result<Vec<String>, String>) {
+    let mut queue = QUEUE.lock().await;
+    let Ok((job, control)) = queue.get_mut(id) else {
+        return;
+    };
+
+    (job.state, job.error) = match &result {
+        Ok(_) => (JobState::Done, None),
+        Err(_) if control.is_cancelled() => (JobState::Cancelled, None),
+        Err(e) => (JobState::Failed, Some(e.clone())),
+    };
+    job.snapshot = None;
+    control.result.send_replace(Some(result));
+    queue.save();
+}
+
+/// Loads the jobs left from the last run. Jobs that were interrupted are rolled back, and
+/// queued again unless they were being cancelled. Runs before the app serves any command, so
+/// nothing can be queued or changed in a profile until recovery is done.
+pub async fn setup() {
+    let mut buf = String::new();
+    let jobs = File::open(get_jobs_path())
+        .and_then(|mut f| f.read_to_string(&mut buf))
+        .ok()
+        .and_then(|_| serde_json::from_str::<Vec<Job>>(&buf).ok())
+        .unwrap_or_default();
+
+    let mut queue = QUEUE.lock().await;
+    for mut job in jobs {
+        if matches!(job.state, JobState::Running | JobState::Paused | JobState::Cancelling) {
+            // A snapshot that's gone was already restored or discarded
+            let restored = match job.snapshot.take() {
+                Some(dir) if Path::new(&dir).exists() => Snapshot::open(&dir).and_then(|s| s.restore()),
+                _ => Ok(()),
+            };
+
+            (job.state, job.error) = match restored {
+                Err(e) => (JobState::Failed, Some(e)),
+                Ok(()) if job.state == JobState::Cancelling => (JobState::Cancelled, None),
+                Ok(()) => (JobState::Queued, None),
+            };
+        }
+
+        JOB_COUNTER.fetch_max(job.id + 1, Ordering::Relaxed);
+        let control = Arc::new(JobControl::new());
+        match job.state {
+            JobState::Done => {
+                control.result.send_replace(Some(Ok(vec![])));
+            }
+            JobState::Failed | JobState::Cancelled => {
+                let error = job.error.clone().unwrap_or_else(|| String::from(CANCELLED));
+                control.result.send_replace(Some(Err(error)));
+            }
+            _ => {}
+        }
+        queue.jobs.push((job, control));
+    }
+    queue.save();
+}
+
+/// Starts running the jobs left from the last run, once the app is there to report on them
+pub async fn start(app: AppHandle) {
+    let _ = APP.set(app);
+
+    let mut queue = QUEUE.lock().await;
+    let profiles: Vec<String> = queue
+        .jobs
+        .iter()
+        .filter(|(j, _)| j.state == JobState::Queued)
+        .map(|(j, _)| j.profile.clone())
+        .collect();
+    for profile in profiles {
+        queue.start_worker(&profile);
+    }
+}
+
+async fn signal_job(id: u64, signal: Signal, state: JobState) -> Result<(), String> {
+    let mut queue = QUEUE.lock().await;
+    let (job, control) = queue.get_mut(id)?;
+    if job.state.is_finished() {
+        return Err(String::from("The job already finished"));
+    }
+    if job.state == JobState::Cancelling {
+        return Err(String::from("The job is being cancelled"));
+    }
+
+    if job.state == JobState::Queued {
+        // Nothing to stop or roll back, it just never runs
+        if signal != Signal::Cancel {
+            return Err(String::from("The job hasn't started yet"));
+        }
+        job.state = JobState::Cancelled;
+        control.result.send_replace(Some(Err(String::from(CANCELLED))));
+    } else {
+        job.state = state;
+        control.signal.send_replace(signal);
+    }
+
+    queue.save();
+    Ok(())
+}
+
+/// Whether `profile` has jobs queued or running
+pub async fn has_active_jobs(profile: &str) -> bool {
+    QUEUE
+        .lock()
+        .await
+        .jobs
+        .iter()
+        .any(|(job, _)| job.profile == profile && !job.state.is_finished())
+}
+
+#[tauri::command]
+pub async fn get_jobs() -> Vec<Job> {
+    QUEUE.lock().await.jobs.iter().map(|(job, _)| job.clone()).collect()
+}
+
+/// Stops a job and puts the profile back the way it was before it started
+#[tauri::command]
+pub async fn cancel_job(id: u64) -> Result<(), String> {
+    signal_job(id, Signal::Cancel, JobState::Cancelling).await
+}
+
+/// Pauses a job once the mod being extracted, if any, is done. Downloads stop right away and
+/// continue where they were on resume.
+#[tauri::command]
+pub async fn pause_job(id: u64) -> Result<(), String> {
+    signal_job(id, Signal::Pause, JobState::Paused).await
+}
+
+#[tauri::command]
+pub async fn resume_job(id: u64) -> Result<(), String> {
+    signal_job(id, Signal::Run, JobState::Running).await
+}
+
+/// Forgets the jobs that finished
+#[tauri::command]
+pub async fn clear_finished_jobs() {
+    let mut queue = QUEUE.lock().await;
+    queue.jobs.retain(|(job, _)| !job.state.is_finished());
+    queue.save();
+}
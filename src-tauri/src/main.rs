@@ -1,11 +1,24 @@
 // Prevents additional console window on Windows in release, DO NOT REMOVE!!
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")]
 
+mod archives;
+mod downloads;
 mod installs;
+mod jobs;
+mod offline;
 mod profiles;
+mod query;
+mod resolver;
+mod search;
+mod server;
+mod snapshot;
+mod sources;
+mod store;
 mod thunderstore;
+mod updates;
 mod userdata;
 mod utils;
+mod version;
 
 use tauri::Manager;
 
@@ -14,8 +27,12 @@ use window_shadows::set_shadow;
 #[tokio::main]
 async fn main() {
     userdata::setup().await;
+    // Recover the install jobs left from the last run, before any command can add one
+    jobs::setup().await;
     tauri::Builder::default()
         .setup(|app| {
+            tauri::async_runtime::spawn(jobs::start(app.handle()));
+
             // Add native shadow to window
             let Some(window) = app.get_window("main") else {
 				return Ok(())
@@ -30,6 +47,27 @@ async fn main() {
             installs::add_manual_install,
             thunderstore::load_package,
             thunderstore::search,
+            offline::set_offline_mode,
+            offline::get_offline_status,
+            sources::get_package_source,
+            sources::set_package_source,
+            sources::get_private_repository,
+            sources::set_private_repository,
+            server::start_server,
+            server::stop_server,
+            server::get_server_status,
+            downloads::get_max_concurrent_downloads,
+            downloads::set_max_concurrent_downloads,
+            archives::get_archive_cache_info,
+            archives::prune_archive_cache,
+            archives::set_archive_cache_limit,
+            store::get_store_mode,
+            store::set_store_mode,
+            jobs::get_jobs,
+            jobs::cancel_job,
+            jobs::pause_job,
+            jobs::resume_job,
+            jobs::clear_finished_jobs,
             profiles::stop_game,
             profiles::get_game_status,
             profiles::play_profile,
@@ -38,9 +76,14 @@ async fn main() {
             profiles::get_profiles,
             profiles::get_profile,
             profiles::get_profile_mods,
+            profiles::preview_install,
             profiles::download_mod,
             profiles::delete_mod,
+            profiles::remove_unused_dependencies,
             profiles::toggle_mod,
+            profiles::set_mod_pinned,
+            updates::check_updates,
+            updates::update_profile,
             utils::show_in_explorer
         ])
         .run(tauri::generate_context!())
@@ -0,0 +1,67 @@
+use serde::{Deserialize, Serialize};
+use chrono::{DateTime, Utc};
+
+use crate::{thunderstore::{self, IndexSource}, userdata};
+
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Feature {
+    /// The package index can't be refreshed, search results may be outdated
+    IndexRefresh,
+    /// There is no package index at all, search returns nothing
+    Search,
+    /// Only archives that were already downloaded can be installed
+    Downloads,
+    /// Profile icons can't be fetched from URLs
+    ProfileIcons,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct OfflineStatus {
+    pub offline: bool,
+    pub reachable: Option<bool>,
+    pub index_source: IndexSource,
+    pub index_fetched_at: Option<DateTime<Utc>>,
+    pub degraded: Vec<Feature>,
+}
+
+pub async fn is_offline() -> bool {
+    userdata::get_settings().await.offline.unwrap_or(false)
+}
+
+#[tauri::command]
+pub async fn set_offline_mode(enabled: bool) {
+    userdata::get_settings().await.offline = Some(enabled);
+    userdata::save_data().await;
+
+    if !enabled {
+        thunderstore::revalidate_package();
+    }
+}
+
+#[tauri::command]
+pub async fn get_offline_status() -> OfflineStatus {
+    let offline = is_offline().await;
+    let status = thunderstore::PACKAGE_STATUS.lock().await.clone();
+    let mut degraded = vec![];
+
+    if offline || status.reachable == Some(false) {
+        degraded.push(Feature::IndexRefresh);
+        degraded.push(Feature::Downloads);
+        degraded.push(Feature::ProfileIcons);
+    }
+    if status.source == IndexSource::None {
+        degraded.push(Feature::Search);
+        if !degraded.contains(&Feature::Downloads) {
+            degraded.push(Feature::Downloads);
+        }
+    }
+
+    OfflineStatus {
+        offline,
+        reachable: status.reachable,
+        index_source: status.source,
+        index_fetched_at: status.fetched_at,
+        degraded,
+    }
+}
@@ -1,28 +1,49 @@
-use crate::{installs, profiles, thunderstore::{self, ModInfo, Version}, userdata::{self, GameStatus}, utils};
-use std::{fs::{File, OpenOptions}, io::{Read, Write}, os::windows::process::CommandExt, path::Path};
+use crate::{archives, downloads::{self, DownloadTracker, ItemState}, installs, jobs::{self, JobControl}, offline, resolver::{self, PlanEntry}, sources, snapshot::Snapshot, store, thunderstore::{self, InstallReason, ModInfo, Origin, Version}, userdata::{self, GameStatus}, utils, version::ModVersion};
+use std::{collections::HashSet, fs::{File, OpenOptions}, io::{Read, Write}, os::windows::process::CommandExt, path::Path, sync::{Arc, Mutex as StdMutex}};
 use glob::glob;
 use serde::{Deserialize, Serialize};
 use regex::{Captures, Regex};
-use futures_util::StreamExt;
-use tauri::Window;
 
-use async_recursion::async_recursion;
 use std::process::{Command, Stdio};
 
 static BEPIN_SUBFOLDERS: [&str; 4] = ["config", "core", "patchers", "plugins"];
 
-#[derive(Clone, Serialize, Deserialize)]
-pub struct DownloadProgress {
-    pub current_mod: String,
-    pub total_progress: f32,
-    pub extract_progress: f32
+#[derive(Serialize, Deserialize)]
+pub struct PreviewEntry {
+    #[serde(flatten)]
+    pub entry: PlanEntry,
+    pub file_size: i64,
+    /// Whether the archive is already on disk and won't be downloaded
+    pub cached: bool,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct VersionChange {
+    pub full_name: String,
+    pub from: ModVersion,
+    pub to: ModVersion,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct InstallPreview {
+    pub entries: Vec<PreviewEntry>,
+    /// Bytes that still have to be downloaded
+    pub download_size: i64,
+    pub upgrades: Vec<VersionChange>,
+    pub downgrades: Vec<VersionChange>,
+    /// Why the install would fail, if it would
+    pub problems: Option<String>,
+    /// What may not work once it's installed
+    pub warnings: Vec<String>,
 }
 
 #[derive(Serialize, Deserialize)]
 pub struct ProfileInfo {
     pub name: String,
     pub icon: Option<String>,
+    /// Mods the user installed, dependencies aren't counted
     pub mods_amount: usize,
+    pub dependencies_amount: usize,
     pub folder: String,
 }
 
@@ -35,7 +56,15 @@ pub struct Profile {
 
 #[derive(Serialize, Deserialize)]
 pub struct Manifest {
-    pub version_number: String
+    #[serde(default)]
+    pub name: Option<String>,
+    pub version_number: String,
+    #[serde(default)]
+    pub description: String,
+    #[serde(default)]
+    pub website_url: String,
+    #[serde(default)]
+    pub dependencies: Vec<String>
 }
 
 #[tauri::command]
@@ -114,7 +143,7 @@ pub async fn play_profile(name: String) {
             .arg("--doorstop-enable")
             .arg("true")
             .arg("--doorstop-target")
-            .arg(&format!("{}\\BepInEx\\core\\BepInEx.Preloader.dll", &profile.folder))
+            .arg(format!("{}\\BepInEx\\core\\BepInEx.Preloader.dll", &profile.folder))
             .creation_flags(utils::CREATE_NO_WINDOW)
             .stdout(Stdio::piped())
             .spawn()
@@ -155,7 +184,8 @@ pub async fn get_profiles() -> Vec<ProfileInfo> {
                 profiles.push(ProfileInfo {
                     name: p.name,
                     icon: p.icon,
-                    mods_amount: mods.len(),
+                    mods_amount: mods.iter().filter(|m| m.install_reason == InstallReason::Requested).count(),
+                    dependencies_amount: mods.iter().filter(|m| m.install_reason == InstallReason::Dependency).count(),
                     folder: path.to_str().unwrap().into()
                 })
             }
@@ -182,16 +212,35 @@ fn save_mods_to_profile(profile: &str, mods: &Vec<ModInfo>) {
         .create(true)
         .truncate(true)
         .write(true)
-        .open(&format!("{app_dir}\\profiles\\{profile}\\mods.yml"))
+        .open(format!("{app_dir}\\profiles\\{profile}\\mods.yml"))
         .unwrap();
     let mods_str = serde_yaml::to_string::<Vec<ModInfo>>(mods).unwrap();
     mods_file.write_all(mods_str.as_bytes()).unwrap();
 }
 
-pub async fn scan_profile_mods(profile: String) -> Vec<ModInfo> {
+fn read_profile_mods(profile: &str) -> Option<Vec<ModInfo>> {
+    let app_dir = userdata::get_app_dir();
+    let mods_file = format!("{app_dir}\\profiles\\{profile}\\mods.yml");
+
+    let mut mods = String::new();
+    File::open(&mods_file).ok()?.read_to_string(&mut mods).ok()?;
+    serde_yaml::from_str::<Vec<ModInfo>>(&mods).ok()
+}
+
+/// Mods of a profile as last saved, or as found on disk if they never were, without writing anything
+pub async fn read_installed_mods(profile: &str) -> Vec<ModInfo> {
+    match read_profile_mods(profile) {
+        Some(mods) => mods,
+        None => collect_profile_mods(profile).await
+    }
+}
+
+/// Reads the mods installed in a profile from its plugins folder, without saving them
+async fn collect_profile_mods(profile: &str) -> Vec<ModInfo> {
     let app_dir = userdata::get_app_dir();
     let plugins_dir = format!("{app_dir}\\profiles\\{profile}\\BepInEx\\plugins");
 
+    let previous_mods = read_profile_mods(profile).unwrap_or_default();
     let mut mods: Vec<ModInfo> = vec![];
 
     if Path::new(&plugins_dir).exists() {
@@ -205,10 +254,36 @@ pub async fn scan_profile_mods(profile: String) -> Vec<ModInfo> {
                 File::open(&manifest_file).unwrap().read_to_end(&mut manifest_buf).unwrap();
                 let manifest_str = String::from_utf8_lossy(&manifest_buf);
                 
-                let manifest = serde_json::from_str::<Manifest>(&manifest_str.trim_start_matches("\u{feff}"));
+                let manifest = serde_json::from_str::<Manifest>(manifest_str.trim_start_matches("\u{feff}"));
                 if let Ok(m) = manifest {
-                    let mut _mod = thunderstore::get_mod(&full_name).await;
-                    let version = _mod.versions.iter().find(|v| v.version_number == m.version_number).unwrap();
+                    // Manifests may write the version differently, like `1.0` for `1.0.0`
+                    let manifest_version = ModVersion::parse(&m.version_number);
+                    let is_manifest_version = |version_number: &str| {
+                        manifest_version.is_some() && ModVersion::parse(version_number) == manifest_version
+                    };
+
+                    let indexed = thunderstore::get_mod(full_name).await.and_then(|_mod| {
+                        let version = _mod.versions.iter().find(|v| is_manifest_version(&v.version_number))?.clone();
+                        Some((_mod, version))
+                    });
+                    let previous = previous_mods
+                        .iter()
+                        .find(|p| p.full_name == full_name && is_manifest_version(&p.version_number));
+
+                    // Prefer the package index, then what we knew about the mod, then its own manifest
+                    let (name, author, description, dependencies) = if let Some((_mod, version)) = indexed {
+                        (_mod.name, _mod.owner, version.description, version.dependencies)
+                    } else if let Some(p) = previous {
+                        (p.name.clone(), p.author.clone(), p.description.clone(), p.dependencies.clone())
+                    } else {
+                        let (author, name) = full_name.split_once('-').unwrap_or(("", full_name));
+                        (
+                            m.name.clone().unwrap_or(name.to_owned()),
+                            author.to_owned(),
+                            m.description.clone(),
+                            m.dependencies.clone()
+                        )
+                    };
 
                     let icon = {
                         let icon_path = format!("{}\\icon.png", path.display());
@@ -222,37 +297,42 @@ pub async fn scan_profile_mods(profile: String) -> Vec<ModInfo> {
                         let mut files = std::fs::read_dir(&path).unwrap();
                         !files.any(|f| f.unwrap().file_name().to_str().unwrap() == ".disabled")
                     };
+                    let (install_reason, pinned) = previous_mods
+                        .iter()
+                        .find(|p| p.full_name == full_name)
+                        .map_or((InstallReason::Requested, false), |p| (p.install_reason, p.pinned));
 
                     mods.push(ModInfo {
-                        name: _mod.name,
-                        full_name: _mod.full_name,
-                        description: version.description.clone(),
-                        author: _mod.owner,
+                        name,
+                        full_name: full_name.to_owned(),
+                        description,
+                        author,
                         version_number: m.version_number,
-                        dependencies: version.dependencies.clone(),
+                        dependencies,
                         folder: path.display().to_string(),
                         icon,
-                        enabled
+                        enabled,
+                        install_reason,
+                        pinned
                     });
                 }
             }
         }
     }
 
-    save_mods_to_profile(&profile, &mods);
+    mods
+}
 
+pub async fn scan_profile_mods(profile: String) -> Vec<ModInfo> {
+    let mods = collect_profile_mods(&profile).await;
+    save_mods_to_profile(&profile, &mods);
     mods
 }
 
 #[tauri::command]
 pub async fn get_profile_mods(profile: String) -> Vec<ModInfo> {
-    let app_dir = userdata::get_app_dir();
-    let mods_file = format!("{app_dir}\\profiles\\{profile}\\mods.yml");
-
-    if let Ok(mut f) = File::open(&mods_file) {
-        let mut mods = String::new();
-        f.read_to_string(&mut mods).unwrap();
-        serde_yaml::from_str::<Vec<ModInfo>>(&mods).unwrap()
+    if let Some(mods) = read_profile_mods(&profile) {
+        mods
     } else {
         scan_profile_mods(profile).await
     }
@@ -264,7 +344,7 @@ pub async fn create_profile(name: String, icon: Option<String>) -> Result<(), St
     let profiles_dir = format!("{app_dir}\\profiles");
 
     let name_pattern = Regex::new(r"^[a-zA-Z0-9_-]+$").unwrap();
-    if let Some(_) = name_pattern.captures(&name) {
+    if name_pattern.is_match(&name) {
         let profile_dir = format!("{profiles_dir}\\{name}");
 
         if Path::new(&profile_dir).exists() {
@@ -275,28 +355,36 @@ pub async fn create_profile(name: String, icon: Option<String>) -> Result<(), St
 
         let icon = match icon {
             Some(icon) => {
-                let (extension, image) = if icon.starts_with("data:image/") {
-                    (
+                let icon = if icon.starts_with("data:image/") {
+                    Some((
                         icon.split_once(';').unwrap().0["data:image/".len()..].to_owned(),
                         image_base64::from_base64(icon)
-                    )
+                    ))
+                } else if offline::is_offline().await {
+                    None // Can't fetch the icon, create the profile without one
                 } else {
-                    (
-                        icon.split('.').last().unwrap().to_owned(),
-                        reqwest::get(&icon).await.unwrap().bytes().await.unwrap().to_vec()
-                    )
+                    let extension = icon.split('.').next_back().unwrap().to_owned();
+                    match reqwest::get(&icon).await {
+                        Ok(r) => r.bytes().await.ok().map(|b| (extension, b.to_vec())),
+                        Err(_) => None
+                    }
                 };
-                let path = format!("{profile_dir}\\icon.{extension}");
-                
-                OpenOptions::new()
-                    .create(true)
-                    .write(true)
-                    .open(&path)
-                    .unwrap()
-                    .write_all(&image)
-                    .unwrap();
-
-                Some(path)
+
+                if let Some((extension, image)) = icon {
+                    let path = format!("{profile_dir}\\icon.{extension}");
+
+                    OpenOptions::new()
+                        .create(true)
+                        .write(true)
+                        .open(&path)
+                        .unwrap()
+                        .write_all(&image)
+                        .unwrap();
+
+                    Some(path)
+                } else {
+                    None
+                }
             }
             None => None
         };
@@ -322,100 +410,319 @@ pub async fn create_profile(name: String, icon: Option<String>) -> Result<(), St
     }
 }
 
+/// Deletes a profile, unless mods are still being installed into it
 #[tauri::command]
-pub async fn delete_profile(name: String) {
+pub async fn delete_profile(name: String) -> Result<(), String> {
+    let _lock = jobs::lock_profile(&name).await;
+    if jobs::has_active_jobs(&name).await {
+        return Err(String::from("Mods are still being installed into this profile"));
+    }
+
     let app_dir = userdata::get_app_dir();
     let profile_dir = format!("{app_dir}\\profiles\\{name}");
-    std::fs::remove_dir_all(profile_dir).unwrap();
+    std::fs::remove_dir_all(&profile_dir).map_err(|e| format!("Failed to delete {profile_dir}: {e}"))
 }
 
-#[tauri::command]
-pub async fn delete_mod(profile: String, name: String) {
-    // Checking dependencies would be a great idea here
+#[derive(Serialize, Deserialize, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum DeleteMode {
+    /// Don't delete mods other mods depend on
+    Refuse,
+    /// Delete it and report which mods are left without it
+    #[default]
+    Warn,
+    /// Delete the mods depending on it as well
+    Cascade,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+pub struct DeleteResult {
+    pub removed: Vec<String>,
+    /// Mods still installed that depended on a removed one
+    pub broken: Vec<String>,
+    /// Mods installed as dependencies of removed mods that nothing else needs, and were kept
+    pub orphans: Vec<String>,
+}
+
+/// Full names of the mods `_mod` depends on
+fn dependency_names(_mod: &ModInfo) -> Vec<String> {
+    _mod.dependencies
+        .iter()
+        .filter_map(|d| thunderstore::parse_mod_version(d).map(|(full_name, _)| full_name))
+        .collect()
+}
+
+/// Mods depending on any of `names`, directly or through other mods
+fn find_dependents(mods: &[ModInfo], names: &HashSet<String>) -> Vec<String> {
+    let mut found: HashSet<String> = names.clone();
+    let mut dependents = vec![];
+
+    loop {
+        let new: Vec<String> = mods
+            .iter()
+            .filter(|m| !found.contains(&m.full_name))
+            .filter(|m| dependency_names(m).iter().any(|d| found.contains(d)))
+            .map(|m| m.full_name.clone())
+            .collect();
+
+        if new.is_empty() {
+            return dependents;
+        }
+        found.extend(new.iter().cloned());
+        dependents.extend(new);
+    }
+}
 
+/// Dependencies of `removed` that none of `remaining` needs anymore
+fn find_orphans(removed: &[&ModInfo], remaining: &[&ModInfo]) -> Vec<String> {
+    let needed: HashSet<String> = remaining.iter().flat_map(|m| dependency_names(m)).collect();
+
+    removed
+        .iter()
+        .flat_map(|m| dependency_names(m))
+        .filter(|d| !needed.contains(d))
+        .filter(|d| remaining.iter().any(|m| m.full_name == *d && m.install_reason == InstallReason::Dependency))
+        .collect::<HashSet<String>>()
+        .into_iter()
+        .collect()
+}
+
+/// Files a mod put outside of its folder
+fn read_external_files(mod_folder: &str) -> Vec<String> {
+    let mut buf = String::new();
+    File::open(format!("{mod_folder}\\external_files.json"))
+        .and_then(|mut f| f.read_to_string(&mut buf))
+        .ok()
+        .and_then(|_| serde_json::from_str::<Vec<String>>(&buf).ok())
+        .unwrap_or_default()
+}
+
+fn remove_mod_files(_mod: &ModInfo) {
+    for f in read_external_files(&_mod.folder) {
+        let _ = std::fs::remove_file(&f);
+    }
+
+    std::fs::remove_dir_all(&_mod.folder).unwrap();
+}
+
+/// Removes the files of the installed version of a mod, saving them in `snapshot` first
+fn remove_installed_files(mod_folder: &str, snapshot: &mut Snapshot) -> Result<(), String> {
+    for f in read_external_files(mod_folder) {
+        snapshot.save(&f)?;
+        let _ = std::fs::remove_file(&f);
+    }
+
+    snapshot.save(mod_folder)?;
+    std::fs::remove_dir_all(mod_folder).map_err(|e| format!("Failed to remove {mod_folder}: {e}"))
+}
+
+#[tauri::command]
+pub async fn delete_mod(profile: String, name: String, mode: Option<DeleteMode>, remove_orphans: Option<bool>) -> Result<DeleteResult, String> {
+    let _lock = jobs::lock_profile(&profile).await;
+    let mode = mode.unwrap_or_default();
     let mods = get_profile_mods(profile.clone()).await;
-    if let Some(_mod) = mods.iter().find(|m| m.full_name == name) {
-        let external_files = format!("{}\\{}", _mod.folder, "external_files.json");
-        if Path::new(&external_files).exists() {
-            let mut buf = String::new();
-            File::open(&external_files).unwrap().read_to_string(&mut buf).unwrap();
+    if !mods.iter().any(|m| m.full_name == name) {
+        return Err(format!("{name} is not installed"));
+    }
 
-            let files = serde_json::from_str::<Vec<String>>(&buf).unwrap();
-            for f in files {
-                let _ = std::fs::remove_file(&f);
-            }
+    let mut to_remove: HashSet<String> = HashSet::from([name.clone()]);
+    let dependents = find_dependents(&mods, &to_remove);
+    let mut result = DeleteResult::default();
+
+    match mode {
+        DeleteMode::Refuse if !dependents.is_empty() => {
+            return Err(format!("{name} is required by {}", dependents.join(", ")));
+        }
+        DeleteMode::Cascade => to_remove.extend(dependents),
+        _ => result.broken = dependents,
+    }
+
+    // Removing mods can leave more of their dependencies unused, keep going until none are
+    loop {
+        let (removed, remaining): (Vec<&ModInfo>, Vec<&ModInfo>) = mods.iter().partition(|m| to_remove.contains(&m.full_name));
+        let orphans = find_orphans(&removed, &remaining);
+
+        if remove_orphans != Some(true) || orphans.is_empty() {
+            result.orphans = orphans;
+            break;
         }
+        to_remove.extend(orphans);
+    }
+
+    for _mod in mods.iter().filter(|m| to_remove.contains(&m.full_name)) {
+        remove_mod_files(_mod);
+        result.removed.push(_mod.full_name.clone());
+    }
 
-        std::fs::remove_dir_all(&_mod.folder).unwrap();
-        scan_profile_mods(profile).await;
+    scan_profile_mods(profile).await;
+    Ok(result)
+}
+
+/// Removes mods installed as dependencies that no other mod needs anymore
+#[tauri::command]
+pub async fn remove_unused_dependencies(profile: String) -> Vec<String> {
+    let _lock = jobs::lock_profile(&profile).await;
+    let mut mods = get_profile_mods(profile.clone()).await;
+    let mut removed = vec![];
+
+    // Removing one can leave its own dependencies unused
+    loop {
+        let needed: HashSet<String> = mods.iter().flat_map(dependency_names).collect();
+        let (unused, kept): (Vec<ModInfo>, Vec<ModInfo>) = mods
+            .into_iter()
+            .partition(|m| m.install_reason == InstallReason::Dependency && !needed.contains(&m.full_name));
+
+        if unused.is_empty() {
+            break;
+        }
+        for _mod in unused {
+            remove_mod_files(&_mod);
+            removed.push(_mod.full_name);
+        }
+        mods = kept;
     }
+
+    scan_profile_mods(profile).await;
+    removed
 }
 
+/// Holds a mod at its installed version, or releases it
 #[tauri::command]
-pub async fn toggle_mod(profile: String, name: String) {
-    // Checking dependencies would be a great idea here
+pub async fn set_mod_pinned(profile: String, name: String, pinned: bool) -> Result<(), String> {
+    let _lock = jobs::lock_profile(&profile).await;
+    let mut mods = get_profile_mods(profile.clone()).await;
+    let Some(_mod) = mods.iter_mut().find(|m| m.full_name == name) else {
+        return Err(format!("{name} is not installed"));
+    };
 
-    let profile = get_profile(profile).await;
-    let mut mods = get_profile_mods(profile.name.clone()).await;
+    _mod.pinned = pinned;
+    save_mods_to_profile(&profile, &mods);
+    Ok(())
+}
+
+#[derive(Serialize, Deserialize, Default)]
+pub struct ToggleResult {
+    /// Mods that were enabled or disabled
+    pub changed: Vec<String>,
+    /// Enabled mods depending on a disabled one, left as they were
+    pub dependents: Vec<String>,
+}
 
-    if let Some(_mod) = mods.iter_mut().find(|m| m.full_name == name) {
-        // Disable/Enable all DLL files
-        for entry in glob(&format!("{}/**/*.dll*", _mod.folder)).unwrap() {
-            let file = entry.unwrap();
-            let filepath = file.display().to_string();
+/// Installed mods `name` needs, directly or through other mods
+fn find_dependencies(mods: &[ModInfo], name: &str) -> Vec<String> {
+    let mut dependencies: Vec<String> = vec![];
+    let mut pending = vec![name.to_owned()];
 
-            if _mod.enabled && !filepath.ends_with(".disabled") {
-                std::fs::rename(&filepath, format!("{filepath}.disabled")).unwrap();
-            } else if !_mod.enabled && filepath.ends_with(".disabled") {
-                std::fs::rename(&filepath, &filepath[0..filepath.len()-9]).unwrap();
+    while let Some(full_name) = pending.pop() {
+        let Some(_mod) = mods.iter().find(|m| m.full_name == full_name) else {
+            continue;
+        };
+        for dep in dependency_names(_mod) {
+            if dep != name && !dependencies.contains(&dep) && mods.iter().any(|m| m.full_name == dep) {
+                dependencies.push(dep.clone());
+                pending.push(dep);
             }
         }
-        
-        // Disable/Enable external files
-        let external_files = format!("{}\\{}", _mod.folder, "external_files.json");
+    }
+
+    dependencies
+}
 
-        if Path::new(&external_files).exists() {
-            let mut buf = String::new();
-            File::open(&external_files).unwrap().read_to_string(&mut buf).unwrap();
+fn set_mod_enabled(_mod: &mut ModInfo, enabled: bool) {
+    if _mod.enabled == enabled {
+        return;
+    }
 
-            let files = serde_json::from_str::<Vec<String>>(&buf).unwrap();
-            let disabled_folder = format!("{}\\.disabled", _mod.folder);
+    // Disable/Enable all DLL files
+    for entry in glob(&format!("{}/**/*.dll*", _mod.folder)).unwrap() {
+        let file = entry.unwrap();
+        let filepath = file.display().to_string();
 
-            if _mod.enabled {
-                // Disable
-                if !Path::new(&disabled_folder).exists() {
-                    std::fs::create_dir(&disabled_folder).unwrap();
-                }
-                for f in files {
-                    let filename = utils::str_skip_to(&f, "BepInEx/");
-                    let relative_path = format!("{}\\.disabled\\{}", _mod.folder, filename);
-                    
-                    if !Path::new(&relative_path).parent().unwrap().exists() {
-                        std::fs::create_dir_all(Path::new(&relative_path).parent().unwrap()).unwrap();
-                    }
-                    if Path::new(&f).exists() {
-                        std::fs::rename(&f, &relative_path).unwrap();
-                    }
+        if !enabled && !filepath.ends_with(".disabled") {
+            std::fs::rename(&filepath, format!("{filepath}.disabled")).unwrap();
+        } else if enabled && filepath.ends_with(".disabled") {
+            std::fs::rename(&filepath, &filepath[0..filepath.len()-9]).unwrap();
+        }
+    }
+    
+    // Disable/Enable external files
+    let external_files = format!("{}\\{}", _mod.folder, "external_files.json");
+
+    if Path::new(&external_files).exists() {
+        let mut buf = String::new();
+        File::open(&external_files).unwrap().read_to_string(&mut buf).unwrap();
+
+        let files = serde_json::from_str::<Vec<String>>(&buf).unwrap();
+        let disabled_folder = format!("{}\\.disabled", _mod.folder);
+
+        if !enabled {
+            // Disable
+            if !Path::new(&disabled_folder).exists() {
+                std::fs::create_dir(&disabled_folder).unwrap();
+            }
+            for f in files {
+                let filename = utils::str_skip_to(&f, "BepInEx/");
+                let relative_path = format!("{}\\.disabled\\{}", _mod.folder, filename);
+                
+                if !Path::new(&relative_path).parent().unwrap().exists() {
+                    std::fs::create_dir_all(Path::new(&relative_path).parent().unwrap()).unwrap();
                 }
-            } else {
-                // Enable
-                if !Path::new(&disabled_folder).exists() {
-                    return; // There aren't any disabled external files
+                if Path::new(&f).exists() {
+                    std::fs::rename(&f, &relative_path).unwrap();
                 }
-                for f in files {
-                    let filename = utils::str_skip_to(&f, "BepInEx/");
-                    let relative_path = format!("{}\\.disabled\\{}", _mod.folder, filename);
+            }
+        } else if Path::new(&disabled_folder).exists() {
+            // Enable, if there are any disabled external files
+            for f in files {
+                let filename = utils::str_skip_to(&f, "BepInEx/");
+                let relative_path = format!("{}\\.disabled\\{}", _mod.folder, filename);
 
-                    if Path::new(&relative_path).exists() {
-                        std::fs::rename(&relative_path, &f).unwrap();
-                    }
+                if Path::new(&relative_path).exists() {
+                    std::fs::rename(&relative_path, &f).unwrap();
                 }
             }
         }
+    }
+
+    _mod.enabled = enabled;
+}
+
+#[tauri::command]
+pub async fn toggle_mod(profile: String, name: String, disable_dependents: Option<bool>) -> Result<ToggleResult, String> {
+    let _lock = jobs::lock_profile(&profile).await;
+    let profile = get_profile(profile).await;
+    let mut mods = get_profile_mods(profile.name.clone()).await;
+    let mut result = ToggleResult::default();
+
+    let Some(_mod) = mods.iter().find(|m| m.full_name == name) else {
+        return Err(format!("{name} is not installed"));
+    };
+
+    // Enabling brings along what the mod needs, disabling can take what needs the mod
+    let (enabled, mut affected) = if _mod.enabled {
+        let enabled_mods: Vec<ModInfo> = mods.iter().filter(|m| m.enabled).cloned().collect();
+        let dependents = find_dependents(&enabled_mods, &HashSet::from([name.clone()]));
+
+        if disable_dependents == Some(true) {
+            (false, dependents)
+        } else {
+            result.dependents = dependents;
+            (false, vec![])
+        }
+    } else {
+        (true, find_dependencies(&mods, &name))
+    };
+    affected.push(name);
 
-        _mod.enabled = !_mod.enabled;
-        save_mods_to_profile(&profile.name, &mods);
+    for _mod in mods.iter_mut().filter(|m| affected.contains(&m.full_name)) {
+        if _mod.enabled != enabled {
+            set_mod_enabled(_mod, enabled);
+            result.changed.push(_mod.full_name.clone());
+        }
     }
+
+    save_mods_to_profile(&profile.name, &mods);
+    Ok(result)
 }
 
 fn fix_path(str: &str) -> String {
@@ -424,6 +731,7 @@ fn fix_path(str: &str) -> String {
     }).to_string()
 }
 
+#[allow(clippy::too_many_arguments)]
 fn resolve_path(
     file_path: &str,
     bepin_subfolder: &str,
@@ -438,13 +746,13 @@ fn resolve_path(
     match bepin_subfolder {
         "plugins" =>  if path_in_dll_folder || file_in_plugins || file_in_root {
             // Save in mod folder
-            format!("{}\\{}", mod_folder, file_path.replace(&dll_folder, ""))
+            format!("{}\\{}", mod_folder, file_path.replace(dll_folder, ""))
         } else {
             // Save in plugins folder
             if dll_folder == "*" {
                 format!("{}\\BepInEx\\plugins\\{}", profile_folder, relative_path)
             } else {
-                format!("{}\\BepInEx\\plugins\\{}", profile_folder, file_path.replace(&dll_folder, ""))
+                format!("{}\\BepInEx\\plugins\\{}", profile_folder, file_path.replace(dll_folder, ""))
             }
         },
         f if BEPIN_SUBFOLDERS.contains(&f) => format!("{}\\BepInEx\\{}\\{}", profile_folder, bepin_subfolder, relative_path),
@@ -452,21 +760,25 @@ fn resolve_path(
     }
 }
 
-fn extract_mod(mod_name: &str, file_path: &str, profile_folder: &str, on_extract: impl Fn(f32) -> ()) {
-    let zip_file = File::open(file_path).unwrap();
-    let mut archive = zip::ZipArchive::new(zip_file).unwrap();
+/// Extracts a mod archive into a profile, saving everything it overwrites in `snapshot` first
+fn extract_mod(mod_name: &str, file_path: &str, profile_folder: &str, snapshot: &mut Snapshot, on_extract: impl Fn(f32) -> Result<(), String>) -> Result<(), String> {
+    let zip_file = File::open(file_path).map_err(|e| format!("Failed to open {file_path}: {e}"))?;
+    let mut archive = zip::ZipArchive::new(zip_file).map_err(|e| format!("Invalid archive {file_path}: {e}"))?;
     let mut external_files: Vec<String> = vec![];
     
     // Make sure mod folder exists
     let mod_folder = format!("{}\\{}\\{}", profile_folder, "BepInEx\\plugins", mod_name);
-    if !Path::new(&mod_folder).exists() && mod_name != "BepInEx-BepInExPack" {
-        std::fs::create_dir_all(&mod_folder).unwrap();
+    if mod_name != "BepInEx-BepInExPack" {
+        snapshot.save(&mod_folder)?;
+        if !Path::new(&mod_folder).exists() {
+            std::fs::create_dir_all(&mod_folder).map_err(|e| format!("Failed to create {mod_folder}: {e}"))?;
+        }
     }
     
     // First iteration to find the folder where the mod .dll is located
     let mut dll_folder = String::from("*");
     for i in 0..archive.len() {
-        let file = archive.by_index(i).unwrap();
+        let file = archive.by_index(i).map_err(|e| e.to_string())?;
         let file_path = file.name();
 
         if file_path.ends_with(".dll") {
@@ -492,12 +804,13 @@ fn extract_mod(mod_name: &str, file_path: &str, profile_folder: &str, on_extract
     println!("Dll folder is {dll_folder}");
 
     // Second iteration to extract files
+    let bepinex_pattern = Regex::new(r"(?i)bepinex/").unwrap();
     let files_amount = archive.len();
     for i in 0..files_amount {
-        let mut file = archive.by_index(i).unwrap();
+        let mut file = archive.by_index(i).map_err(|e| e.to_string())?;
 
-        // Report current extraction progress
-        on_extract(i as f32 / files_amount as f32 * 100.0);
+        // Report current extraction progress, which is also where it can be cancelled
+        on_extract(i as f32 / files_amount as f32 * 100.0)?;
         
         // Fix path in case of incorrect naming
         let file_path = fix_path(file.name());
@@ -506,9 +819,14 @@ fn extract_mod(mod_name: &str, file_path: &str, profile_folder: &str, on_extract
         if mod_name == "BepInEx-BepInExPack" && file_path.starts_with("BepInExPack/") && file_path != "BepInExPack/" {
             let path = format!("{}\\{}", profile_folder, file_path.chars().skip(12).collect::<String>()); // Remove "BepInExPack/"
             if file.is_dir() {
-                std::fs::create_dir_all(&path).unwrap();
-            } else {
-                utils::extract_file(&mut file, &path);
+                // Only folders that don't exist yet, the files inside are saved one by one
+                if !Path::new(&path).exists() {
+                    snapshot.save(&path)?;
+                }
+                std::fs::create_dir_all(&path).map_err(|e| format!("Failed to create {path}: {e}"))?;
+            } else if !store::keeps_user_config(profile_folder, &path) {
+                snapshot.save(&path)?;
+                utils::extract_file(&mut file, &path).map_err(|e| format!("Failed to extract {path}: {e}"))?;
             }
             continue;
         }
@@ -523,7 +841,7 @@ fn extract_mod(mod_name: &str, file_path: &str, profile_folder: &str, on_extract
         let file_in_plugins = parent.ends_with("plugins");
         let file_in_root = parent.display().to_string() == "";
 
-        let bepin_path = if Regex::new(r"(?i)bepinex/").unwrap().is_match(&file_path) {
+        let bepin_path = if bepinex_pattern.is_match(&file_path) {
             let sub_path = &file_path["BepInEx/".len()..];
             sub_path.split_once('/').unwrap()
         } else if let Some((bepin_subfolder, relative_path)) = file_path.split_once('/') {
@@ -542,7 +860,7 @@ fn extract_mod(mod_name: &str, file_path: &str, profile_folder: &str, on_extract
             &file_path,
             bepin_path.0,
             bepin_path.1,
-            &profile_folder,
+            profile_folder,
             &mod_folder,
             &dll_folder,
             path_in_dll_folder,
@@ -550,131 +868,262 @@ fn extract_mod(mod_name: &str, file_path: &str, profile_folder: &str, on_extract
             file_in_root
         );
 
+        // Still recorded, so it goes away with the mod like before
+        let kept = store::keeps_user_config(profile_folder, &outpath);
         let file_stored_outside = !outpath.starts_with(&mod_folder);
         if file_stored_outside {
             external_files.push(outpath.clone().replace("\\", "/"));
         }
+        if kept {
+            continue;
+        }
+        if file_stored_outside {
+            snapshot.save(&outpath)?;
+        }
 
-        utils::extract_file(&mut file, &outpath);
+        utils::extract_file(&mut file, &outpath).map_err(|e| format!("Failed to extract {outpath}: {e}"))?;
     }
 
     // Store all created external files to later remove/disable the mod
-    if external_files.len() > 0 {
-        let mut ef_file = OpenOptions::new()
+    if !external_files.is_empty() {
+        let buf = serde_json::to_string::<Vec<String>>(&external_files).unwrap();
+        OpenOptions::new()
             .create(true)
             .truncate(true)
             .write(true)
-            .open(&format!("{}\\{}", mod_folder, "external_files.json"))
-            .unwrap();
-        let buf = serde_json::to_string::<Vec<String>>(&external_files).unwrap();
-        ef_file.write_all(buf.as_bytes()).unwrap();
+            .open(format!("{}\\{}", mod_folder, "external_files.json"))
+            .and_then(|mut f| f.write_all(buf.as_bytes()))
+            .map_err(|e| format!("Failed to save external files of {mod_name}: {e}"))?;
     }
-}
 
-fn get_absolute_version(version_number: &str) -> u32 {
-    let n_split: Vec<&str> = version_number.split('.').collect();
-    n_split[0].parse::<u32>().unwrap()*100 +
-    n_split[1].parse::<u32>().unwrap()*10 +
-    n_split[0].parse::<u32>().unwrap()
+    Ok(())
 }
 
-#[async_recursion]
-pub async fn get_dependencies(version_name: &str, deps: &mut Vec<Version>, profile_mods: &Vec<ModInfo>) {
-	let (full_name, version_number) = thunderstore::parse_mod_version(version_name);
-    let mut has_newer_version = false;
-
-    // Check on found dependencies
-    let ind = deps.iter().position(|m| m.full_name.contains(&full_name));
-    if let Some(i) = ind {
-        if get_absolute_version(&version_number) > get_absolute_version(&deps[i].version_number) {
-            has_newer_version = true;
-        } else {
-            return; // Don't add dependency with a lower version than previously found one
+/// Marks the mods named in `version_names` as requested, and the ones that weren't in
+/// `previous_mods` as dependencies
+fn mark_install_reasons(mods: &mut [ModInfo], previous_mods: &[ModInfo], version_names: &[String]) {
+    let requested: Vec<String> = version_names
+        .iter()
+        .filter_map(|v| thunderstore::parse_mod_version(v).map(|(full_name, _)| full_name))
+        .collect();
+
+    for _mod in mods {
+        if requested.contains(&_mod.full_name) {
+            _mod.install_reason = InstallReason::Requested;
+        } else if !previous_mods.iter().any(|p| p.full_name == _mod.full_name) {
+            _mod.install_reason = InstallReason::Dependency;
         }
     }
+}
 
-    // Check installed dependency (if exists)
-    if let Some(i) = profile_mods.iter().position(|m| m.full_name == full_name) {
-        if get_absolute_version(&version_number) <= get_absolute_version(&profile_mods[i].version_number) {
-            return; // Don't add dependency with an equal or lower version than the installed one
-        }
-    }
+async fn is_archive_cached(version: &Version) -> bool {
+    version.origin == Origin::Private || archives::find(version).await.is_some()
+}
 
-    if let Some(_mod) = thunderstore::get_mod_version(version_name).await {
-        let dependencies = _mod.dependencies.clone();
+/// Shows what `download_mod` would do, without writing anything
+#[tauri::command]
+pub async fn preview_install(profile_name: String, version_name: String) -> InstallPreview {
+    let profile_mods = read_installed_mods(&profile_name).await;
+    let plan = resolver::resolve(&[version_name], &profile_mods).await;
+
+    let mut preview = InstallPreview {
+        entries: vec![],
+        download_size: 0,
+        upgrades: vec![],
+        downgrades: vec![],
+        problems: plan.problems(),
+        warnings: plan.warnings(),
+    };
 
-        if has_newer_version {
-            deps[ind.unwrap()] = _mod;
-        } else {
-            deps.insert(0, _mod);
+    for entry in plan.entries {
+        let (file_size, cached) = match &entry.version {
+            Some(v) => (v.file_size, is_archive_cached(v).await),
+            None => (0, false)
+        };
+        if entry.is_download() && !cached {
+            preview.download_size += file_size;
         }
 
-        for dep in &dependencies {
-            get_dependencies(dep, deps, profile_mods).await;
+        if let (true, Some(from), Some(to)) = (entry.is_download(), &entry.installed, &entry.target) {
+            let change = VersionChange {
+                full_name: entry.full_name.clone(),
+                from: from.clone(),
+                to: to.clone(),
+            };
+            if to > from {
+                preview.upgrades.push(change);
+            } else if to < from {
+                preview.downgrades.push(change);
+            }
         }
+
+        preview.entries.push(PreviewEntry { entry, file_size, cached });
     }
+
+    preview
 }
 
-#[tauri::command]
-pub async fn download_mod(window: Window, profile_name: String, version_name: String) {
-    let profile = profiles::get_profile(profile_name.clone()).await;
-    let profile_mods = get_profile_mods(profile_name.clone()).await;
-    let mut mods_to_download: Vec<Version> = vec![];
-
-    get_dependencies(&version_name, &mut mods_to_download, &profile_mods).await;
-
-    let mods_amount = mods_to_download.len();
-    for i in 0..mods_amount {
-        let _mod = mods_to_download.get(i).unwrap();
-        let temp_dir = std::env::temp_dir();
-        let temp_file = format!("{}{}.zip", temp_dir.display(), &_mod.full_name);
-        let total_progress = i as f32 / mods_amount as f32 * 100.0;
-
-        window.emit("download_progress", DownloadProgress {
-            current_mod: format!("Downloading {}...", &_mod.name),
-            total_progress,
-            extract_progress: 0.0
-        }).unwrap();
-
-        if !Path::new(&temp_file).exists() {
-            println!("Downloading {}...", _mod.full_name);
-            if let Ok(r) = reqwest::get(&_mod.download_url).await {
-                let mut file = OpenOptions::new().write(true).create(true).open(&temp_file).unwrap();
-                let mut stream = r.bytes_stream();
-        
-                while let Some(chunk) = stream.next().await {
-                    file.write_all(&chunk.unwrap()).unwrap();
+fn get_mod_name(version: &Version) -> String {
+    let mut name_parts = version.full_name.split('-').collect::<Vec<&str>>();
+    name_parts.pop();
+    name_parts.join("-")
+}
+
+/// Downloads `versions` and extracts them into a profile, in order. With store mode they're
+/// extracted into the store as soon as they're downloaded, and linked into the profile in order.
+/// Files are only touched on blocking threads. Gives `snapshot` back along with the result.
+async fn install_versions(tracker: Arc<DownloadTracker>, control: Arc<JobControl>, profile: &Profile, versions: &[Version], snapshot: Snapshot) -> (Snapshot, Result<(), String>) {
+    let store_mode = store::is_store_mode().await;
+
+    // Private archives can change under the same version, so they aren't kept in the store
+    let uses_store = move |version: &Version| store_mode && version.origin != Origin::Private;
+    let prepare = {
+        let tracker = tracker.clone();
+        let control = control.clone();
+        move |i: usize, version: Version, archive_path: String| {
+            let tracker = tracker.clone();
+            let control = control.clone();
+            async move {
+                if !uses_store(&version) {
+                    return Ok(archive_path);
                 }
+
+                // Extraction can't be paused, so wait before starting it
+                control.checkpoint().await?;
+                tracker.set_state(i, ItemState::Extracting);
+                let mod_name = get_mod_name(&version);
+                store::get_or_extract(&version, move |folder| {
+                    // Nothing in a fresh store folder needs to be put back
+                    let mut store_snapshot = Snapshot::new()?;
+                    let extracted = extract_mod(&mod_name, &archive_path, folder, &mut store_snapshot, |e_p| {
+                        tracker.set_extract_progress(i, e_p);
+                        control.check()
+                    });
+                    store_snapshot.discard();
+                    extracted
+                })
+                .await
+            }
+        }
+    };
+
+    let snapshot = Arc::new(StdMutex::new(snapshot));
+    let on_downloaded = |i: usize, _mod: Version, path: String| {
+        let (tracker, control, snapshot) = (tracker.clone(), control.clone(), snapshot.clone());
+        let profile_folder = profile.folder.clone();
+
+        let install = move || {
+            let mut snapshot = snapshot.lock().unwrap();
+            let mod_name = get_mod_name(&_mod);
+
+            // Nothing of the version being replaced may stay behind
+            let mod_folder = format!("{}\\BepInEx\\plugins\\{}", profile_folder, mod_name);
+            if Path::new(&mod_folder).exists() {
+                remove_installed_files(&mod_folder, &mut snapshot)?;
+            }
+
+            if uses_store(&_mod) {
+                store::link_version(&path, &profile_folder, &mod_name, &mut snapshot)
             } else {
-                println!("Failed to get mod.");
+                extract_mod(&mod_name, &path, &profile_folder, &mut snapshot, |e_p| {
+                    tracker.set_extract_progress(i, e_p);
+                    control.check()
+                })
+            }
+        };
+        async move { tauri::async_runtime::spawn_blocking(install).await.map_err(|e| e.to_string())? }
+    };
+
+    let installed = downloads::download_all(tracker.clone(), control.clone(), versions, prepare, on_downloaded).await;
+
+    // Every step was waited for, nothing else holds the snapshot anymore
+    let snapshot = Arc::try_unwrap(snapshot)
+        .ok()
+        .expect("the snapshot is only shared while installing")
+        .into_inner()
+        .unwrap_or_else(|e| e.into_inner());
+    (snapshot, installed)
+}
+
+/// Installs `version_names` and everything they need into a profile, once the jobs queued for
+/// it before are done. If anything fails, the profile is put back the way it was. With
+/// `requested`, the mods are marked as installed by the user. Returns the versions that were
+/// installed.
+pub async fn install_mods(profile_name: &str, version_names: &[String], requested: bool) -> Result<Vec<String>, String> {
+    let job_id = jobs::enqueue(profile_name, version_names, requested).await;
+    jobs::wait(job_id).await
+}
+
+pub async fn run_install(job_id: u64, control: Arc<JobControl>, profile_name: &str, version_names: &[String], requested: bool) -> Result<Vec<String>, String> {
+    let profile = get_profile(profile_name.to_owned()).await;
+    let profile_mods = get_profile_mods(profile_name.to_owned()).await;
+
+    let plan = resolver::resolve(version_names, &profile_mods).await;
+    if let Some(problems) = plan.problems() {
+        return Err(problems);
+    }
+    for warning in plan.warnings() {
+        println!("Warning: {warning}");
+    }
+    let mods_to_download = plan.downloads();
+
+    let source = sources::get_source().await;
+
+    // Offline installs can only use archives that were downloaded before, or private ones
+    if offline::is_offline().await && source.is_remote() {
+        let mut missing = vec![];
+        for version in &mods_to_download {
+            if !is_archive_cached(version).await {
+                missing.push(version.full_name.clone());
             }
         }
 
-        let mut name_parts = _mod.full_name.split('-').collect::<Vec<&str>>();
-        name_parts.pop();
+        if !missing.is_empty() {
+            return Err(format!("Not available offline: {}", missing.join(", ")));
+        }
+    }
 
-        extract_mod(&name_parts.join("-"), &temp_file, &profile.folder, |e_p| {
-            window.emit("download_progress", DownloadProgress {
-                current_mod: format!("Extracting {}...", &_mod.name),
-                total_progress,
-                extract_progress: e_p
-            }).unwrap();
-        });
+    let pins = archives::pin(&mods_to_download);
+    let tracker = Arc::new(DownloadTracker::new(job_id, &mods_to_download));
+    let mut snapshot = Snapshot::new()?;
+    jobs::set_snapshot(job_id, Some(snapshot.dir().to_owned())).await;
+    snapshot.save(&format!("{}\\mods.yml", profile.folder))?;
+
+    let (snapshot, installed) = install_versions(tracker.clone(), control.clone(), &profile, &mods_to_download, snapshot).await;
+    if let Err(e) = installed {
+        tracker.message("Rolling back...", 100.0);
+
+        let restored = snapshot.restore();
+        if restored.is_ok() && control.is_cancelled() {
+            tracker.message("Cancelled", 100.0);
+        }
+        return match restored {
+            Ok(()) => Err(e),
+            Err(restore_error) => Err(format!("{e}\n{restore_error}"))
+        };
     }
+    jobs::set_snapshot(job_id, None).await;
+    snapshot.discard();
+
+    tracker.message("Scanning profile mods...", 99.9);
 
-    window.emit("download_progress", DownloadProgress {
-        current_mod: String::from("Scanning profile mods..."),
-        total_progress: 99.9,
-        extract_progress: 100.0
-    }).unwrap();
+    let mut mods = scan_profile_mods(profile_name.to_owned()).await;
+    mark_install_reasons(&mut mods, &profile_mods, if requested { version_names } else { &[] });
+    save_mods_to_profile(profile_name, &mods);
 
-    scan_profile_mods(profile_name).await;
+    // The cache is only trimmed once nothing still needs the archives
+    drop(pins);
+    if let Err(e) = archives::enforce_limit().await {
+        println!("{e}");
+    }
 
-    window.emit("download_progress", DownloadProgress {
-        current_mod: String::from("Done :)"),
-        total_progress: 100.0,
-        extract_progress: 100.0
-    }).unwrap();
+    tracker.message("Done :)", 100.0);
 
     println!("Done!");
+    Ok(mods_to_download.into_iter().map(|v| v.full_name).collect())
+}
+
+#[tauri::command]
+pub async fn download_mod(profile_name: String, version_name: String) -> Result<(), String> {
+    install_mods(&profile_name, &[version_name], true).await.map(|_| ())
 }
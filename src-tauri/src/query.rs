@@ -0,0 +1,370 @@
+use chrono::NaiveDate;
+
+use crate::{search, thunderstore::Mod};
+
+enum Comparison<T> {
+    Less(T),
+    LessOrEqual(T),
+    Greater(T),
+    GreaterOrEqual(T),
+    Equal(T),
+}
+
+enum Condition {
+    Author(String),
+    Category(String),
+    Dependency(String),
+    /// Every token must be a word of the mod's text, the way the search index splits it
+    Text(Vec<String>),
+    Phrase(String),
+    Updated(Comparison<NaiveDate>),
+    Created(Comparison<NaiveDate>),
+    Downloads(Comparison<i64>),
+    Rating(Comparison<i64>),
+    Deprecated(bool),
+    Nsfw(bool),
+}
+
+struct Filter {
+    negated: bool,
+    condition: Condition,
+}
+
+/// A clause of the query as typed, like `-author:"some one"`
+struct Clause {
+    negated: bool,
+    field: Option<String>,
+    value: String,
+    quoted: bool,
+}
+
+/// A search query compiled into filters over the package. Words without a field are left as
+/// `text` for the search index to rank, everything else must hold for a mod to be listed.
+pub struct Query {
+    pub text: String,
+    filters: Vec<Filter>,
+}
+
+impl<T: PartialOrd> Comparison<T> {
+    fn parse(value: &str, parse: impl Fn(&str) -> Option<T>) -> Option<Comparison<T>> {
+        let (comparison, operand): (fn(T) -> Comparison<T>, &str) = if let Some(v) = value.strip_prefix(">=") {
+            (Comparison::GreaterOrEqual, v)
+        } else if let Some(v) = value.strip_prefix("<=") {
+            (Comparison::LessOrEqual, v)
+        } else if let Some(v) = value.strip_prefix('>') {
+            (Comparison::Greater, v)
+        } else if let Some(v) = value.strip_prefix('<') {
+            (Comparison::Less, v)
+        } else {
+            (Comparison::Equal, value.strip_prefix('=').unwrap_or(value))
+        };
+
+        parse(operand.trim()).map(comparison)
+    }
+
+    fn test(&self, value: &T) -> bool {
+        match self {
+            Comparison::Less(v) => value < v,
+            Comparison::LessOrEqual(v) => value <= v,
+            Comparison::Greater(v) => value > v,
+            Comparison::GreaterOrEqual(v) => value >= v,
+            Comparison::Equal(v) => value == v,
+        }
+    }
+}
+
+/// Lowercases `text` and collapses everything that isn't a letter or a digit into single spaces
+fn normalize(text: &str) -> String {
+    text.to_lowercase()
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|w| !w.is_empty())
+        .collect::<Vec<&str>>()
+        .join(" ")
+}
+
+fn searchable_text(m: &Mod) -> String {
+    let description = m.versions.first().map_or("", |v| v.description.as_str());
+    format!("{} {} {}", m.name, m.owner, description)
+}
+
+fn parse_date(value: &str) -> Option<NaiveDate> {
+    NaiveDate::parse_from_str(value, "%Y-%m-%d").ok()
+}
+
+fn parse_number(value: &str) -> Option<i64> {
+    value.replace(['_', ','], "").parse::<i64>().ok()
+}
+
+fn parse_bool(value: &str) -> Option<bool> {
+    match value.to_lowercase().as_str() {
+        "true" | "yes" => Some(true),
+        "false" | "no" => Some(false),
+        _ => None,
+    }
+}
+
+fn lex(query: &str) -> Vec<Clause> {
+    let mut clauses = vec![];
+    let mut chars = query.chars().peekable();
+
+    loop {
+        while chars.peek().map_or(false, |c| c.is_whitespace()) {
+            chars.next();
+        }
+        let Some(&first) = chars.peek() else {
+            break;
+        };
+
+        let negated = first == '-';
+        if negated {
+            chars.next();
+        }
+
+        let mut field = None;
+        let mut value = String::new();
+        let mut quoted = false;
+        let mut in_quotes = false;
+
+        while let Some(&c) = chars.peek() {
+            if c.is_whitespace() && !in_quotes {
+                break;
+            }
+            chars.next();
+
+            match c {
+                '"' => {
+                    in_quotes = !in_quotes;
+                    quoted = true;
+                }
+                ':' if field.is_none() && !quoted && !value.is_empty() => {
+                    field = Some(std::mem::take(&mut value));
+                }
+                _ => value.push(c),
+            }
+        }
+
+        if !value.is_empty() || field.is_some() {
+            clauses.push(Clause { negated, field, value, quoted });
+        }
+    }
+
+    clauses
+}
+
+impl Query {
+    pub fn parse(query: &str) -> Result<Query, String> {
+        let mut text: Vec<String> = vec![];
+        let mut filters = vec![];
+
+        for clause in lex(query) {
+            let Clause { negated, field, value, quoted } = clause;
+            let invalid = |field: &str| format!("Invalid value for {field}: \"{value}\"");
+
+            let condition = match field.as_ref().map(|f| f.to_lowercase()).as_deref() {
+                None => {
+                    if quoted {
+                        Condition::Phrase(normalize(&value))
+                    } else if negated {
+                        Condition::Text(search::tokenize(&value, false))
+                    } else {
+                        text.push(value);
+                        continue;
+                    }
+                }
+                Some("author") | Some("owner") => Condition::Author(value.to_lowercase()),
+                Some("category") => Condition::Category(value.to_lowercase()),
+                Some("dep") | Some("dependency") => Condition::Dependency(value.to_lowercase()),
+                Some("updated") => Condition::Updated(Comparison::parse(&value, parse_date).ok_or_else(|| invalid("updated"))?),
+                Some("created") => Condition::Created(Comparison::parse(&value, parse_date).ok_or_else(|| invalid("created"))?),
+                Some("downloads") => Condition::Downloads(Comparison::parse(&value, parse_number).ok_or_else(|| invalid("downloads"))?),
+                Some("rating") => Condition::Rating(Comparison::parse(&value, parse_number).ok_or_else(|| invalid("rating"))?),
+                Some("deprecated") => Condition::Deprecated(parse_bool(&value).ok_or_else(|| invalid("deprecated"))?),
+                Some("nsfw") => Condition::Nsfw(parse_bool(&value).ok_or_else(|| invalid("nsfw"))?),
+                Some(_) => {
+                    // Not a field we know, it's just a word with a colon
+                    let word = format!("{}:{}", field.unwrap(), value);
+                    if negated {
+                        Condition::Text(search::tokenize(&word, false))
+                    } else {
+                        text.push(word);
+                        continue;
+                    }
+                }
+            };
+
+            // Phrases also help ranking, as long as they're wanted
+            if let Condition::Phrase(phrase) = &condition {
+                if !negated {
+                    text.push(phrase.clone());
+                }
+            }
+
+            filters.push(Filter { negated, condition });
+        }
+
+        Ok(Query { text: text.join(" "), filters })
+    }
+
+    pub fn matches(&self, m: &Mod) -> bool {
+        self.filters.iter().all(|f| f.condition.matches(m) != f.negated)
+    }
+
+    /// Like `matches`, without the filters on `field`, to count what other values of it would list
+    pub fn matches_except(&self, m: &Mod, field: &str) -> bool {
+        self.filters
+            .iter()
+            .filter(|f| f.condition.field() != Some(field))
+            .all(|f| f.condition.matches(m) != f.negated)
+    }
+}
+
+impl Condition {
+    /// Field of the filters facets are counted for
+    fn field(&self) -> Option<&str> {
+        match self {
+            Condition::Deprecated(_) => Some("deprecated"),
+            Condition::Nsfw(_) => Some("nsfw"),
+            _ => None,
+        }
+    }
+
+    fn matches(&self, m: &Mod) -> bool {
+        match self {
+            Condition::Author(author) => m.owner.to_lowercase() == *author,
+            Condition::Category(category) => m.categories.iter().any(|c| c.to_lowercase() == *category),
+            Condition::Dependency(dependency) => m.versions.first().map_or(false, |v| {
+                v.dependencies.iter().any(|d| {
+                    // Dependencies are Owner-Name-Version, match either Owner-Name or Name
+                    let d = d.to_lowercase();
+                    let full_name = d.rsplit_once('-').map_or(d.as_str(), |(n, _)| n);
+                    let name = full_name.split_once('-').map_or(full_name, |(_, n)| n);
+                    full_name == dependency || name == dependency
+                })
+            }),
+            Condition::Text(tokens) => {
+                let words = search::tokenize(&searchable_text(m), true);
+                !tokens.is_empty() && tokens.iter().all(|t| words.contains(t))
+            }
+            Condition::Phrase(phrase) => normalize(&searchable_text(m)).contains(phrase.as_str()),
+            Condition::Updated(c) => c.test(&m.date_updated.date_naive()),
+            Condition::Created(c) => c.test(&m.date_created.date_naive()),
+            Condition::Downloads(c) => c.test(&m.versions.iter().map(|v| v.downloads as i64).sum()),
+            Condition::Rating(c) => c.test(&(m.rating_score as i64)),
+            Condition::Deprecated(deprecated) => m.is_deprecated == *deprecated,
+            Condition::Nsfw(nsfw) => m.has_nsfw_content == *nsfw,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::thunderstore::{Origin, Version};
+    use chrono::{TimeZone, Utc};
+
+    fn test_mod(full_name: &str, description: &str, dependencies: &[&str]) -> Mod {
+        let (owner, name) = full_name.split_once('-').unwrap();
+        let date = Utc.with_ymd_and_hms(2024, 1, 15, 0, 0, 0).unwrap();
+        Mod {
+            categories: vec![String::from("Mods")],
+            date_created: date,
+            date_updated: date,
+            full_name: full_name.to_owned(),
+            has_nsfw_content: false,
+            is_deprecated: false,
+            is_pinned: false,
+            name: name.to_owned(),
+            owner: owner.to_owned(),
+            package_url: String::new(),
+            rating_score: 10,
+            uuid4: String::new(),
+            versions: vec![Version {
+                date_created: date,
+                dependencies: dependencies.iter().map(|d| d.to_string()).collect(),
+                description: description.to_owned(),
+                download_url: String::new(),
+                downloads: 500,
+                file_size: 0,
+                full_name: format!("{full_name}-1.0.0"),
+                icon: String::new(),
+                is_active: true,
+                name: name.to_owned(),
+                uuid4: String::new(),
+                version_number: String::from("1.0.0"),
+                website_url: String::new(),
+                readme: None,
+                origin: Origin::Public,
+            }],
+            origin: Origin::Public,
+        }
+    }
+
+    fn matches(query: &str, m: &Mod) -> bool {
+        Query::parse(query).unwrap().matches(m)
+    }
+
+    #[test]
+    fn plain_words_are_left_for_ranking() {
+        let query = Query::parse(r#"more suits author:x "big ship""#).unwrap();
+        assert_eq!(query.text, "more suits big ship");
+    }
+
+    #[test]
+    fn lexes_fields_negation_and_quotes() {
+        let clauses = lex(r#"-author:"some one" word"#);
+        assert_eq!(clauses.len(), 2);
+        assert!(clauses[0].negated && clauses[0].quoted);
+        assert_eq!(clauses[0].field.as_deref(), Some("author"));
+        assert_eq!(clauses[0].value, "some one");
+        assert!(!clauses[1].negated && clauses[1].field.is_none());
+    }
+
+    #[test]
+    fn field_filters() {
+        let m = test_mod("Someone-ShipLoot", "Shows the loot value", &["BepInEx-BepInExPack-5.4.2100"]);
+        assert!(matches("author:someone", &m));
+        assert!(!matches("-author:someone", &m));
+        assert!(matches("category:mods", &m));
+        assert!(matches("dep:BepInExPack dep:BepInEx-BepInExPack", &m));
+        assert!(matches("downloads:>=500 rating:<20", &m));
+        assert!(!matches("downloads:>500", &m));
+        assert!(matches("updated:>2024-01-01 created:<=2024-01-15", &m));
+        assert!(matches("deprecated:false nsfw:no", &m));
+    }
+
+    #[test]
+    fn invalid_values_are_errors() {
+        assert!(Query::parse("downloads:lots").is_err());
+        assert!(Query::parse("updated:yesterday").is_err());
+        assert!(Query::parse("nsfw:maybe").is_err());
+    }
+
+    #[test]
+    fn exclusions_match_whole_tokens() {
+        let m = test_mod("Someone-Builder", "Lets you build anything", &[]);
+        // "ui" is inside "build" but isn't a word of it
+        assert!(matches("-ui", &m));
+        assert!(!matches("-build", &m));
+        // Compound names are split like the search index does
+        let m = test_mod("Someone-MoreUI", "Bigger menus", &[]);
+        assert!(!matches("-ui", &m));
+    }
+
+    #[test]
+    fn phrases_match_normalized_text() {
+        let m = test_mod("Someone-ShipLoot", "Shows the loot value, on the ship", &[]);
+        assert!(matches(r#""loot value on the""#, &m));
+        assert!(!matches(r#""value loot""#, &m));
+        assert!(!matches(r#"-"the ship""#, &m));
+    }
+
+    #[test]
+    fn facet_fields_can_be_left_out() {
+        let mut m = test_mod("Someone-Old", "", &[]);
+        m.is_deprecated = true;
+        let query = Query::parse("deprecated:false author:someone").unwrap();
+        assert!(!query.matches(&m));
+        assert!(query.matches_except(&m, "deprecated"));
+        assert!(!Query::parse("author:other").unwrap().matches_except(&m, "deprecated"));
+    }
+}
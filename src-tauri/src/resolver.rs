@@ -0,0 +1,636 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{thunderstore::{self, ModInfo, Package, Version}, version::ModVersion};
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Requirement {
+    /// Version name of the mod that depends on it, `None` when it was requested
+    pub required_by: Option<String>,
+    pub version_number: ModVersion,
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum PlanAction {
+    Install,
+    Upgrade { from: ModVersion },
+    Downgrade { from: ModVersion },
+    Reinstall,
+    Keep,
+    Missing,
+    Conflict,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct PlanEntry {
+    pub full_name: String,
+    pub action: PlanAction,
+    pub installed: Option<ModVersion>,
+    /// Version the profile ends up with
+    pub target: Option<ModVersion>,
+    /// Version to download, for installs and upgrades
+    pub version: Option<Version>,
+    /// Why the mod is part of the plan
+    pub requirements: Vec<Requirement>,
+    /// Why this action was picked
+    pub reason: String,
+    /// Things that may not work out, which don't stop the install
+    pub warnings: Vec<String>,
+}
+
+/// Everything installing some mods into a profile involves, dependencies first
+#[derive(Serialize, Deserialize, Clone)]
+pub struct InstallPlan {
+    pub entries: Vec<PlanEntry>,
+}
+
+impl Requirement {
+    fn describe(&self) -> String {
+        match &self.required_by {
+            Some(by) => format!("{by} requires {}", self.version_number),
+            None => format!("{} was requested", self.version_number),
+        }
+    }
+}
+
+impl PlanEntry {
+    pub fn is_download(&self) -> bool {
+        matches!(
+            self.action,
+            PlanAction::Install | PlanAction::Upgrade { .. } | PlanAction::Downgrade { .. } | PlanAction::Reinstall
+        )
+    }
+
+    /// Whether it was asked for, rather than pulled in by another mod. Entries without
+    /// requirements are version names that couldn't be read, which were asked for too.
+    pub fn is_requested(&self) -> bool {
+        self.requirements.is_empty() || self.requirements.iter().any(|r| r.required_by.is_none())
+    }
+
+    /// Conflicts, and mods that were asked for but can't be found, stop the install. Missing
+    /// dependencies only warn, like mods on Thunderstore that depend on removed packages.
+    pub fn is_problem(&self) -> bool {
+        match self.action {
+            PlanAction::Conflict => true,
+            PlanAction::Missing => self.is_requested(),
+            _ => false,
+        }
+    }
+}
+
+impl InstallPlan {
+    /// Versions to download and extract, in order
+    pub fn downloads(&self) -> Vec<Version> {
+        self.entries
+            .iter()
+            .filter(|e| e.is_download())
+            .filter_map(|e| e.version.clone())
+            .collect()
+    }
+
+    /// Describes why the plan can't be carried out, if it can't
+    pub fn problems(&self) -> Option<String> {
+        let problems: Vec<String> = self
+            .entries
+            .iter()
+            .filter(|e| e.is_problem())
+            .map(|e| format!("{}: {}", e.full_name, e.reason))
+            .collect();
+
+        if problems.is_empty() {
+            None
+        } else {
+            Some(problems.join("\n"))
+        }
+    }
+
+    /// Everything that may not work out once the plan is carried out
+    pub fn warnings(&self) -> Vec<String> {
+        let mut warnings = vec![];
+        for entry in &self.entries {
+            if entry.action == PlanAction::Missing && !entry.is_problem() {
+                warnings.push(format!("{}: {}", entry.full_name, entry.reason));
+            }
+            warnings.extend(entry.warnings.iter().map(|w| format!("{}: {w}", entry.full_name)));
+        }
+        warnings
+    }
+}
+
+fn find_exact_version<'a>(package: &'a Package, full_name: &str, version_number: &ModVersion) -> Option<&'a Version> {
+    package
+        .mods
+        .get(full_name)?
+        .versions
+        .iter()
+        .find(|v| ModVersion::parse(&v.version_number).as_ref() == Some(version_number))
+}
+
+/// Finds `version_number` of a mod, or the newest compatible version if that one is gone
+fn find_version<'a>(package: &'a Package, full_name: &str, version_number: &ModVersion) -> Option<&'a Version> {
+    find_exact_version(package, full_name, version_number).or_else(|| {
+        package.mods.get(full_name)?.versions.iter().find(|v| {
+            ModVersion::parse(&v.version_number).map_or(false, |p| p.satisfies(version_number))
+        })
+    })
+}
+
+/// Picks what to do with a mod. `dependents` are the requirements installed mods that stay
+/// have on it.
+fn decide(
+    package: &Package,
+    full_name: &str,
+    requirements: &[Requirement],
+    installed: Option<&ModVersion>,
+    pinned: bool,
+    dependents: &[Requirement],
+) -> PlanEntry {
+    let wanted = requirements.iter().map(|r| &r.version_number).max().unwrap();
+    // Versions asked for explicitly are installed as they are, older or not
+    let requested = requirements.iter().find(|r| r.required_by.is_none()).map(|r| &r.version_number);
+    let mut entry = PlanEntry {
+        full_name: full_name.to_owned(),
+        action: PlanAction::Keep,
+        installed: installed.cloned(),
+        target: None,
+        version: None,
+        requirements: requirements.to_vec(),
+        reason: String::new(),
+        warnings: vec![],
+    };
+
+    if let Some(installed) = installed.filter(|i| pinned && requested != Some(*i)) {
+        entry.target = Some(installed.clone());
+        let moving: Vec<String> = requirements
+            .iter()
+            .filter(|r| r.version_number > *installed || r.required_by.is_none())
+            .map(|r| r.describe())
+            .collect();
+
+        if moving.is_empty() {
+            entry.reason = format!("Pinned at {installed}");
+        } else {
+            entry.action = PlanAction::Conflict;
+            entry.reason = format!("Pinned at {installed}, but {}", moving.join(", "));
+            return entry;
+        }
+    } else if let Some(requested) = requested {
+        if let Some(version) = find_exact_version(package, full_name, requested) {
+            entry.target = Some(requested.clone());
+            entry.version = Some(version.clone());
+            (entry.action, entry.reason) = match installed {
+                None => (PlanAction::Install, String::from("Not installed")),
+                Some(i) if i < requested => (
+                    PlanAction::Upgrade { from: i.clone() },
+                    format!("Installed {i} is older than {requested}"),
+                ),
+                Some(i) if i > requested => (
+                    PlanAction::Downgrade { from: i.clone() },
+                    format!("Installed {i} is newer than {requested}"),
+                ),
+                Some(_) => (PlanAction::Reinstall, format!("{requested} is already installed")),
+            };
+        } else {
+            entry.action = PlanAction::Missing;
+            entry.reason = format!("{requested} is not available");
+        }
+    } else if let Some(installed) = installed.filter(|i| *i >= wanted) {
+        entry.target = Some(installed.clone());
+        entry.reason = format!("Installed {installed} is recent enough");
+    } else if let Some(version) = find_version(package, full_name, wanted) {
+        entry.target = ModVersion::parse(&version.version_number);
+        entry.version = Some(version.clone());
+        match installed {
+            Some(installed) => {
+                entry.action = PlanAction::Upgrade { from: installed.clone() };
+                entry.reason = format!("Installed {installed} is older than {wanted}");
+            }
+            None => {
+                entry.action = PlanAction::Install;
+                entry.reason = String::from("Not installed");
+            }
+        }
+    } else {
+        entry.action = PlanAction::Missing;
+        entry.reason = if package.mods.contains_key(full_name) {
+            format!("No version compatible with {wanted} is available")
+        } else {
+            String::from("Not found in the package index")
+        };
+    }
+
+    // Requirements on another major version, or on a newer version than the one picked, may not
+    // be met. Modpacks often mix majors of shared libraries, so the newest one is installed.
+    if let Some(target) = entry.target.clone() {
+        let unmet: Vec<String> = requirements
+            .iter()
+            .filter(|r| !target.same_major(&r.version_number) || target < r.version_number)
+            .map(|r| r.describe())
+            .collect();
+        if !unmet.is_empty() {
+            entry.warnings.push(format!("{target} may not work for: {}", unmet.join(", ")));
+        }
+
+        // Going back a version, or on to another major, can break the mods that stay installed
+        let breaking = match &entry.action {
+            PlanAction::Downgrade { .. } => true,
+            PlanAction::Upgrade { from } => !from.same_major(&target),
+            _ => false,
+        };
+        if breaking {
+            let broken: Vec<String> = dependents
+                .iter()
+                .filter(|r| !target.same_major(&r.version_number) || target < r.version_number)
+                .map(|r| r.describe())
+                .collect();
+
+            if !broken.is_empty() {
+                entry.action = PlanAction::Conflict;
+                entry.reason = format!("{target} would break installed mods: {}", broken.join(", "));
+            }
+        }
+    }
+
+    entry
+}
+
+/// Resolves everything needed to install `version_names` into a profile with `profile_mods`
+pub async fn resolve(version_names: &[String], profile_mods: &[ModInfo]) -> InstallPlan {
+    let package = thunderstore::get_package().await;
+    resolve_in(&package, version_names, profile_mods)
+}
+
+pub fn resolve_in(package: &Package, version_names: &[String], profile_mods: &[ModInfo]) -> InstallPlan {
+    let installed: HashMap<&str, ModVersion> = profile_mods
+        .iter()
+        .filter_map(|m| Some((m.full_name.as_str(), ModVersion::parse(&m.version_number)?)))
+        .collect();
+    let pinned: HashSet<&str> = profile_mods
+        .iter()
+        .filter(|m| m.pinned)
+        .map(|m| m.full_name.as_str())
+        .collect();
+
+    // Installed mods that depend on each mod, with what they require of it
+    let mut dependents: HashMap<String, Vec<(&str, Requirement)>> = HashMap::new();
+    for _mod in profile_mods {
+        for dependency in &_mod.dependencies {
+            if let Some((dep_name, dep_version)) = thunderstore::parse_mod_version(dependency) {
+                dependents.entry(dep_name).or_default().push((_mod.full_name.as_str(), Requirement {
+                    required_by: Some(format!("{}-{}", _mod.full_name, _mod.version_number)),
+                    version_number: dep_version,
+                }));
+            }
+        }
+    }
+
+    let mut invalid = vec![];
+    let mut roots = vec![];
+    for version_name in version_names {
+        match thunderstore::parse_mod_version(version_name) {
+            Some(root) => roots.push(root),
+            None => invalid.push(PlanEntry {
+                full_name: version_name.clone(),
+                action: PlanAction::Missing,
+                installed: None,
+                target: None,
+                version: None,
+                requirements: vec![],
+                reason: String::from("Not a valid version name"),
+                warnings: vec![],
+            }),
+        }
+    }
+
+    // Dependencies of a mod are taken from the version it would be installed with. When a
+    // newer version turns out to be required, everything is resolved again from that one.
+    let mut floors: HashMap<String, ModVersion> = HashMap::new();
+
+    loop {
+        let mut requirements: HashMap<String, Vec<Requirement>> = HashMap::new();
+        let mut dependencies: HashMap<String, Vec<String>> = HashMap::new();
+        let mut expanded: HashMap<String, ModVersion> = HashMap::new();
+        // Installed mods that get another version, whose old requirements go away
+        let mut replaced: HashSet<String> = HashSet::new();
+        let mut queue: VecDeque<(String, Requirement)> = roots
+            .iter()
+            .map(|(full_name, version_number)| {
+                (full_name.clone(), Requirement { required_by: None, version_number: version_number.clone() })
+            })
+            .collect();
+
+        while let Some((full_name, requirement)) = queue.pop_front() {
+            let reqs = requirements.entry(full_name.clone()).or_default();
+            reqs.push(requirement);
+
+            if expanded.contains_key(&full_name) {
+                continue;
+            }
+
+            let wanted = reqs
+                .iter()
+                .map(|r| &r.version_number)
+                .chain(floors.get(&full_name))
+                .max()
+                .unwrap()
+                .clone();
+
+            // Mods that are kept already have their dependencies. Requested ones bring the
+            // dependencies of the exact version asked for, even when reinstalled.
+            let requested = reqs.iter().find(|r| r.required_by.is_none()).map(|r| r.version_number.clone());
+            let installed_version = installed.get(full_name.as_str());
+            let version = match &requested {
+                _ if pinned.contains(full_name.as_str()) && requested.as_ref() != installed_version => None,
+                Some(requested) => find_exact_version(package, &full_name, requested),
+                None if installed_version.map_or(false, |i| *i >= wanted) => None,
+                None => find_version(package, &full_name, &wanted),
+            };
+            expanded.insert(full_name.clone(), wanted);
+
+            if let Some(version) = version {
+                if installed_version.is_some() {
+                    replaced.insert(full_name.clone());
+                }
+                for dependency in &version.dependencies {
+                    if let Some((dep_name, dep_version)) = thunderstore::parse_mod_version(dependency) {
+                        dependencies.entry(full_name.clone()).or_default().push(dep_name.clone());
+                        queue.push_back((dep_name, Requirement {
+                            required_by: Some(version.full_name.clone()),
+                            version_number: dep_version,
+                        }));
+                    }
+                }
+            }
+        }
+
+        let mut outdated = false;
+        for (full_name, reqs) in &requirements {
+            let wanted = reqs.iter().map(|r| &r.version_number).max().unwrap();
+            if wanted > &expanded[full_name] {
+                floors.insert(full_name.clone(), wanted.clone());
+                outdated = true;
+            }
+        }
+        if outdated {
+            continue;
+        }
+
+        // Order dependencies before their dependents
+        let mut order: Vec<String> = vec![];
+        let mut visited: HashSet<String> = HashSet::new();
+        let mut stack: Vec<(String, bool)> = roots.iter().rev().map(|(n, _)| (n.clone(), false)).collect();
+
+        while let Some((full_name, children_done)) = stack.pop() {
+            if children_done {
+                order.push(full_name);
+                continue;
+            }
+            if !visited.insert(full_name.clone()) {
+                continue;
+            }
+
+            stack.push((full_name.clone(), true));
+            for dep in dependencies.get(&full_name).into_iter().flatten().rev() {
+                if !visited.contains(dep) {
+                    stack.push((dep.clone(), false));
+                }
+            }
+        }
+
+        let mut entries = invalid;
+        for full_name in order {
+            let reqs = &requirements[&full_name];
+            let is_pinned = pinned.contains(full_name.as_str());
+            let mod_dependents: Vec<Requirement> = dependents
+                .get(&full_name)
+                .into_iter()
+                .flatten()
+                .filter(|(dependent, _)| !replaced.contains(*dependent))
+                .map(|(_, r)| r.clone())
+                .collect();
+            entries.push(decide(package, &full_name, reqs, installed.get(full_name.as_str()), is_pinned, &mod_dependents));
+        }
+
+        return InstallPlan { entries };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::thunderstore::{InstallReason, Mod, Origin};
+    use chrono::Utc;
+
+    /// A package with `(full_name, version, dependencies)` entries, newest versions first
+    fn package(versions: &[(&str, &str, &[&str])]) -> Package {
+        let mut mods: HashMap<String, Mod> = HashMap::new();
+        for (full_name, version_number, dependencies) in versions {
+            let (owner, name) = full_name.split_once('-').unwrap();
+            let m = mods.entry(full_name.to_string()).or_insert_with(|| Mod {
+                categories: vec![],
+                date_created: Utc::now(),
+                date_updated: Utc::now(),
+                full_name: full_name.to_string(),
+                has_nsfw_content: false,
+                is_deprecated: false,
+                is_pinned: false,
+                name: name.to_owned(),
+                owner: owner.to_owned(),
+                package_url: String::new(),
+                rating_score: 0,
+                uuid4: String::new(),
+                versions: vec![],
+                origin: Origin::Public,
+            });
+            m.versions.push(Version {
+                date_created: Utc::now(),
+                dependencies: dependencies.iter().map(|d| d.to_string()).collect(),
+                description: String::new(),
+                download_url: String::new(),
+                downloads: 0,
+                file_size: 0,
+                full_name: format!("{full_name}-{version_number}"),
+                icon: String::new(),
+                is_active: true,
+                name: name.to_owned(),
+                uuid4: String::new(),
+                version_number: version_number.to_string(),
+                website_url: String::new(),
+                readme: None,
+                origin: Origin::Public,
+            });
+        }
+        for m in mods.values_mut() {
+            m.versions.sort_by_key(|v| std::cmp::Reverse(ModVersion::parse(&v.version_number)));
+        }
+        Package { categories: vec![], mods }
+    }
+
+    fn installed(full_name: &str, version_number: &str, dependencies: &[&str], pinned: bool) -> ModInfo {
+        ModInfo {
+            name: full_name.split_once('-').unwrap().1.to_owned(),
+            full_name: full_name.to_owned(),
+            description: String::new(),
+            author: String::new(),
+            version_number: version_number.to_owned(),
+            dependencies: dependencies.iter().map(|d| d.to_string()).collect(),
+            folder: String::new(),
+            icon: None,
+            enabled: true,
+            install_reason: InstallReason::Requested,
+            pinned,
+        }
+    }
+
+    fn resolve(package: &Package, names: &[&str], profile_mods: &[ModInfo]) -> InstallPlan {
+        let names: Vec<String> = names.iter().map(|n| n.to_string()).collect();
+        resolve_in(package, &names, profile_mods)
+    }
+
+    fn entry<'a>(plan: &'a InstallPlan, full_name: &str) -> &'a PlanEntry {
+        plan.entries.iter().find(|e| e.full_name == full_name).unwrap()
+    }
+
+    fn v(version: &str) -> ModVersion {
+        ModVersion::parse(version).unwrap()
+    }
+
+    #[test]
+    fn installs_dependencies_first() {
+        let package = package(&[
+            ("A-Mod", "1.0.0", &["B-Lib-1.0.0"]),
+            ("B-Lib", "1.0.0", &["C-Core-1.0.0"]),
+            ("C-Core", "1.0.0", &[]),
+        ]);
+        let plan = resolve(&package, &["A-Mod-1.0.0"], &[]);
+
+        let order: Vec<&str> = plan.entries.iter().map(|e| e.full_name.as_str()).collect();
+        assert_eq!(order, ["C-Core", "B-Lib", "A-Mod"]);
+        assert!(plan.entries.iter().all(|e| e.action == PlanAction::Install));
+        assert!(plan.problems().is_none());
+    }
+
+    #[test]
+    fn keeps_installed_versions_that_are_recent_enough() {
+        let package = package(&[
+            ("A-Mod", "1.0.0", &["B-Lib-1.1.0"]),
+            ("B-Lib", "1.3.0", &[]),
+            ("B-Lib", "1.2.0", &[]),
+        ]);
+        let plan = resolve(&package, &["A-Mod-1.0.0"], &[installed("B-Lib", "1.2.0", &[], false)]);
+        assert_eq!(entry(&plan, "B-Lib").action, PlanAction::Keep);
+
+        let plan = resolve(&package, &["A-Mod-1.0.0"], &[installed("B-Lib", "1.0.0", &[], false)]);
+        assert_eq!(entry(&plan, "B-Lib").action, PlanAction::Upgrade { from: v("1.0.0") });
+        assert_eq!(entry(&plan, "B-Lib").target, Some(v("1.3.0")));
+    }
+
+    #[test]
+    fn resolves_again_when_a_newer_version_is_required() {
+        let package = package(&[
+            ("A-Mod", "1.0.0", &["B-Lib-1.0.0", "C-Other-1.0.0"]),
+            ("C-Other", "1.0.0", &["B-Lib-1.1.0"]),
+            ("B-Lib", "1.1.0", &["D-New-1.0.0"]),
+            ("B-Lib", "1.0.0", &[]),
+            ("D-New", "1.0.0", &[]),
+        ]);
+        let plan = resolve(&package, &["A-Mod-1.0.0"], &[]);
+
+        assert_eq!(entry(&plan, "B-Lib").target, Some(v("1.1.0")));
+        assert_eq!(entry(&plan, "D-New").action, PlanAction::Install);
+    }
+
+    #[test]
+    fn mixed_majors_install_the_newest_with_a_warning() {
+        let package = package(&[
+            ("A-Mod", "1.0.0", &["C-Lib-1.0.0"]),
+            ("B-Mod", "1.0.0", &["C-Lib-2.0.0"]),
+            ("C-Lib", "2.0.0", &[]),
+            ("C-Lib", "1.0.0", &[]),
+        ]);
+        let plan = resolve(&package, &["A-Mod-1.0.0", "B-Mod-1.0.0"], &[]);
+
+        let lib = entry(&plan, "C-Lib");
+        assert_eq!(lib.action, PlanAction::Install);
+        assert_eq!(lib.target, Some(v("2.0.0")));
+        assert!(plan.problems().is_none());
+        assert_eq!(plan.warnings().len(), 1);
+    }
+
+    #[test]
+    fn missing_dependencies_only_warn() {
+        let package = package(&[("A-Mod", "1.0.0", &["Gone-Lib-1.0.0"])]);
+        let plan = resolve(&package, &["A-Mod-1.0.0"], &[]);
+
+        assert_eq!(entry(&plan, "Gone-Lib").action, PlanAction::Missing);
+        assert!(plan.problems().is_none());
+        assert_eq!(plan.downloads().len(), 1);
+        assert_eq!(plan.warnings().len(), 1);
+    }
+
+    #[test]
+    fn missing_requested_versions_fail() {
+        let package = package(&[("A-Mod", "1.0.0", &[])]);
+
+        assert!(resolve(&package, &["A-Mod-2.0.0"], &[]).problems().is_some());
+        assert!(resolve(&package, &["Nope-Mod-1.0.0"], &[]).problems().is_some());
+        assert!(resolve(&package, &["not a version"], &[]).problems().is_some());
+    }
+
+    #[test]
+    fn requested_versions_are_installed_as_asked() {
+        let package = package(&[("A-Mod", "2.0.0", &[]), ("A-Mod", "1.0.0", &[])]);
+
+        let plan = resolve(&package, &["A-Mod-1.0.0"], &[installed("A-Mod", "2.0.0", &[], false)]);
+        assert_eq!(entry(&plan, "A-Mod").action, PlanAction::Downgrade { from: v("2.0.0") });
+
+        let plan = resolve(&package, &["A-Mod-1.0.0"], &[installed("A-Mod", "1.0.0", &[], false)]);
+        assert_eq!(entry(&plan, "A-Mod").action, PlanAction::Reinstall);
+    }
+
+    #[test]
+    fn major_upgrades_that_break_dependents_conflict() {
+        let package = package(&[
+            ("C-Lib", "2.0.0", &[]),
+            ("C-Lib", "1.1.0", &[]),
+            ("C-Lib", "1.0.0", &[]),
+            ("A-Mod", "2.0.0", &["C-Lib-2.0.0"]),
+            ("A-Mod", "1.0.0", &["C-Lib-1.0.0"]),
+        ]);
+        let profile = [
+            installed("A-Mod", "1.0.0", &["C-Lib-1.0.0"], false),
+            installed("C-Lib", "1.0.0", &[], false),
+        ];
+
+        let plan = resolve(&package, &["C-Lib-2.0.0"], &profile);
+        assert_eq!(entry(&plan, "C-Lib").action, PlanAction::Conflict);
+        assert!(plan.problems().is_some());
+
+        // Within a major the dependent is fine
+        let plan = resolve(&package, &["C-Lib-1.1.0"], &profile);
+        assert!(plan.problems().is_none());
+
+        // So is a dependent that's upgraded along with it
+        let plan = resolve(&package, &["C-Lib-2.0.0", "A-Mod-2.0.0"], &profile);
+        assert!(plan.problems().is_none());
+    }
+
+    #[test]
+    fn pinned_mods_are_kept_unless_something_needs_newer() {
+        let package = package(&[
+            ("A-Mod", "1.0.0", &["C-Lib-1.0.0"]),
+            ("B-Mod", "1.0.0", &["C-Lib-1.2.0"]),
+            ("C-Lib", "1.2.0", &[]),
+            ("C-Lib", "1.1.0", &[]),
+        ]);
+        let profile = [installed("C-Lib", "1.1.0", &[], true)];
+
+        let plan = resolve(&package, &["A-Mod-1.0.0"], &profile);
+        assert_eq!(entry(&plan, "C-Lib").action, PlanAction::Keep);
+
+        let plan = resolve(&package, &["B-Mod-1.0.0"], &profile);
+        assert_eq!(entry(&plan, "C-Lib").action, PlanAction::Conflict);
+    }
+}
@@ -0,0 +1,297 @@
+use std::{collections::{BTreeMap, HashMap, HashSet}, ops::Bound};
+
+use once_cell::sync::Lazy;
+use tokio::sync::RwLock;
+
+use crate::thunderstore::Package;
+
+// BM25 parameters
+const K1: f32 = 1.2;
+const B: f32 = 0.75;
+
+// Field weights, a word in a mod's name says more than one in its description
+const NAME_WEIGHT: f32 = 3.0;
+const OWNER_WEIGHT: f32 = 2.0;
+const DESCRIPTION_WEIGHT: f32 = 1.0;
+
+// Score factors of terms matched loosely
+const PREFIX_FACTOR: f32 = 0.7;
+const TYPO_FACTOR: f32 = 0.4;
+const MAX_EXPANSIONS: usize = 64;
+
+pub static INDEX: Lazy<RwLock<SearchIndex>> = Lazy::new(|| RwLock::new(SearchIndex::default()));
+
+struct Posting {
+    doc: u32,
+    /// Term frequency, weighted by the field the term was found in
+    tf: f32,
+}
+
+/// Inverted index over the mods of the package
+#[derive(Default)]
+pub struct SearchIndex {
+    docs: Vec<String>,
+    lengths: Vec<f32>,
+    avg_length: f32,
+    terms: BTreeMap<String, Vec<Posting>>,
+}
+
+/// Splits `text` into lowercase words. With `split_compounds`, words like `MoreCompany` also
+/// produce their parts so that searching `company` finds them.
+pub fn tokenize(text: &str, split_compounds: bool) -> Vec<String> {
+    let mut tokens = vec![];
+
+    for word in text.split(|c: char| !c.is_alphanumeric()).filter(|w| !w.is_empty()) {
+        tokens.push(word.to_lowercase());
+
+        if split_compounds {
+            let parts = split_camel_case(word);
+            if parts.len() > 1 {
+                tokens.extend(parts.iter().map(|(_, p)| p.to_lowercase()));
+            }
+        }
+    }
+
+    tokens
+}
+
+/// Parts of a compound word, with their byte offset in it
+fn split_camel_case(word: &str) -> Vec<(usize, &str)> {
+    let chars: Vec<(usize, char)> = word.char_indices().collect();
+    let mut parts = vec![];
+    let mut start = 0;
+
+    for i in 1..chars.len() {
+        let (idx, c) = chars[i];
+        let prev = chars[i - 1].1;
+        let next_is_lower = chars.get(i + 1).map_or(false, |(_, n)| n.is_lowercase());
+
+        // "moreCompany", "v2", and the "S" starting "Server" in "HTTPServer"
+        let boundary = (prev.is_lowercase() && c.is_uppercase())
+            || prev.is_alphabetic() != c.is_alphabetic()
+            || (prev.is_uppercase() && c.is_uppercase() && next_is_lower);
+
+        if boundary {
+            parts.push((start, &word[start..idx]));
+            start = idx;
+        }
+    }
+
+    parts.push((start, &word[start..]));
+    parts
+}
+
+/// How many typos a word can have and still match `term`
+fn max_typos(term: &str) -> usize {
+    match term.chars().count() {
+        0..=3 => 0,
+        4..=7 => 1,
+        _ => 2,
+    }
+}
+
+/// Edit distance between `a` and `b` counting swapped letters as one edit, or `None` if it's
+/// over `max`
+fn edit_distance(a: &str, b: &str, max: usize) -> Option<usize> {
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+    let mut prev2 = vec![0; b.len() + 1];
+    let mut prev: Vec<usize> = (0..=b.len()).collect();
+    let mut cur = vec![0; b.len() + 1];
+
+    for i in 1..=a.len() {
+        cur[0] = i;
+        for j in 1..=b.len() {
+            let cost = if a[i - 1] == b[j - 1] { 0 } else { 1 };
+            cur[j] = (prev[j] + 1).min(cur[j - 1] + 1).min(prev[j - 1] + cost);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                cur[j] = cur[j].min(prev2[j - 2] + 1);
+            }
+        }
+        if cur.iter().min().map_or(true, |&d| d > max) {
+            return None; // Every path is already too far
+        }
+        std::mem::swap(&mut prev2, &mut prev);
+        std::mem::swap(&mut prev, &mut cur);
+    }
+
+    Some(prev[b.len()]).filter(|&d| d <= max)
+}
+
+/// Character ranges of `text` matched by `terms`, the same way the index matches them
+pub fn highlight(text: &str, terms: &[String]) -> Vec<(usize, usize)> {
+    let mut spans: Vec<(usize, usize)> = vec![];
+    let mut chars = 0;
+
+    for (i, word) in text.split(|c: char| !c.is_alphanumeric()).enumerate() {
+        // Every separator takes one character
+        let word_start = chars + i;
+        chars += word.chars().count();
+
+        let mut candidates = vec![(0, word)];
+        let parts = split_camel_case(word);
+        if parts.len() > 1 {
+            candidates.extend(parts);
+        }
+
+        for (offset, part) in candidates {
+            let start = word_start + word[..offset].chars().count();
+            let lower = part.to_lowercase();
+
+            for term in terms {
+                if lower.starts_with(term.as_str()) {
+                    spans.push((start, start + term.chars().count()));
+                } else if max_typos(term) > 0 && edit_distance(term, &lower, max_typos(term)).is_some() {
+                    spans.push((start, start + part.chars().count()));
+                }
+            }
+        }
+    }
+
+    // Merge overlapping matches
+    spans.sort();
+    let mut merged: Vec<(usize, usize)> = vec![];
+    for (start, end) in spans {
+        match merged.last_mut() {
+            Some(last) if start <= last.1 => last.1 = last.1.max(end),
+            _ => merged.push((start, end)),
+        }
+    }
+    merged
+}
+
+impl SearchIndex {
+    pub fn build(package: &Package) -> SearchIndex {
+        let mut index = SearchIndex::default();
+
+        // Sorted so equally scored mods always come out in the same order
+        let mut full_names: Vec<&String> = package.mods.keys().collect();
+        full_names.sort();
+
+        for full_name in full_names {
+            let m = &package.mods[full_name];
+            let doc = index.docs.len() as u32;
+            let description = m.versions.first().map_or("", |v| v.description.as_str());
+
+            let mut tfs: HashMap<String, f32> = HashMap::new();
+            let mut length = 0.0;
+            for (text, weight) in [
+                (m.name.as_str(), NAME_WEIGHT),
+                (m.owner.as_str(), OWNER_WEIGHT),
+                (description, DESCRIPTION_WEIGHT),
+            ] {
+                for token in tokenize(text, true) {
+                    *tfs.entry(token).or_default() += weight;
+                    length += weight;
+                }
+            }
+
+            for (term, tf) in tfs {
+                index.terms.entry(term).or_default().push(Posting { doc, tf });
+            }
+            index.docs.push(full_name.clone());
+            index.lengths.push(length);
+        }
+
+        index.avg_length = index.lengths.iter().sum::<f32>() / index.lengths.len().max(1) as f32;
+        index
+    }
+
+    /// Indexed terms `term` stands for, with the factor their score is multiplied by
+    fn expand(&self, term: &str) -> Vec<(&str, f32)> {
+        let mut expansions = vec![];
+
+        if let Some((t, _)) = self.terms.get_key_value(term) {
+            expansions.push((t.as_str(), 1.0));
+        }
+
+        // Prefixes, so results show up while typing. Closer completions are worth more.
+        let completions = self
+            .terms
+            .range::<str, _>((Bound::Excluded(term), Bound::Unbounded))
+            .take_while(|(t, _)| t.starts_with(term))
+            .take(MAX_EXPANSIONS);
+        for (t, _) in completions {
+            expansions.push((t.as_str(), PREFIX_FACTOR * term.len() as f32 / t.len() as f32));
+        }
+
+        // Typos, for words long enough to tell them apart from other words
+        let max_distance = max_typos(term);
+        let Some(first) = term.chars().next() else {
+            return expansions;
+        };
+        if max_distance > 0 {
+            let term_length = term.chars().count();
+            let first = first.to_string();
+            let candidates = self
+                .terms
+                .range::<str, _>((Bound::Included(first.as_str()), Bound::Unbounded))
+                .take_while(|(t, _)| t.starts_with(&first));
+
+            for (t, _) in candidates {
+                if t.starts_with(term) || t.chars().count().abs_diff(term_length) > max_distance {
+                    continue;
+                }
+                if edit_distance(term, t, max_distance).is_some() {
+                    expansions.push((t.as_str(), TYPO_FACTOR));
+                }
+            }
+        }
+
+        expansions
+    }
+
+    /// Mods matching every word of `query`, best first. An empty query matches every mod.
+    pub fn search(&self, query: &str) -> Vec<(&str, f32)> {
+        let mut terms = tokenize(query, false);
+        let mut seen = HashSet::new();
+        terms.retain(|t| seen.insert(t.clone()));
+
+        if terms.is_empty() {
+            return self.docs.iter().map(|d| (d.as_str(), 0.0)).collect();
+        }
+
+        let doc_count = self.docs.len() as f32;
+        let mut scores: Option<HashMap<u32, f32>> = None;
+
+        for term in &terms {
+            let mut term_scores: HashMap<u32, f32> = HashMap::new();
+
+            for (indexed, factor) in self.expand(term) {
+                let postings = &self.terms[indexed];
+                let df = postings.len() as f32;
+                let idf = (1.0 + (doc_count - df + 0.5) / (df + 0.5)).ln();
+
+                for p in postings {
+                    let norm = 1.0 - B + B * self.lengths[p.doc as usize] / self.avg_length;
+                    let score = factor * idf * p.tf * (K1 + 1.0) / (p.tf + K1 * norm);
+
+                    // A word only counts once per mod, through its best match
+                    let best = term_scores.entry(p.doc).or_default();
+                    if score > *best {
+                        *best = score;
+                    }
+                }
+            }
+
+            scores = Some(match scores {
+                None => term_scores,
+                Some(mut scores) => {
+                    scores.retain(|doc, _| term_scores.contains_key(doc));
+                    for (doc, score) in scores.iter_mut() {
+                        *score += term_scores[doc];
+                    }
+                    scores
+                }
+            });
+        }
+
+        let mut hits: Vec<(&str, f32)> = scores
+            .unwrap_or_default()
+            .into_iter()
+            .map(|(doc, score)| (self.docs[doc as usize].as_str(), score))
+            .collect();
+        hits.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap().then_with(|| a.0.cmp(b.0)));
+        hits
+    }
+}
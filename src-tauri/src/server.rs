@@ -0,0 +1,390 @@
+use std::{
+    collections::{hash_map::DefaultHasher, HashMap, HashSet},
+    fs::OpenOptions,
+    hash::{Hash, Hasher},
+    io::Write,
+    net::SocketAddr,
+    path::Path,
+    sync::{Arc, Weak},
+    time::Duration,
+};
+
+use chrono::{DateTime, Utc};
+use futures_util::future::{self, Either};
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+    sync::{watch, Mutex},
+};
+
+use crate::{archives, thunderstore::{self, Mod, Origin, Package}, userdata, version::ModVersion};
+
+const DEFAULT_ADDRESS: &str = "0.0.0.0:8734";
+const MAX_LOG_ENTRIES: usize = 500;
+const MAX_REQUEST_SIZE: usize = 8192;
+
+static SERVER: Lazy<Mutex<Option<RunningServer>>> = Lazy::new(|| Mutex::new(None));
+static ACCESS_LOG: Lazy<Mutex<Vec<AccessLogEntry>>> = Lazy::new(|| Mutex::new(vec![]));
+/// Index bodies already built, by the host they were requested through
+static SERVED_INDEXES: Lazy<Mutex<HashMap<String, ServedIndex>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+
+struct RunningServer {
+    address: String,
+    shutdown: watch::Sender<bool>,
+}
+
+/// The index as served for a package and a state of the archive cache
+struct ServedIndex {
+    package: Weak<Package>,
+    archives: u64,
+    etag: String,
+    body: Arc<Vec<u8>>,
+}
+
+struct Request {
+    method: String,
+    path: String,
+    host: Option<String>,
+    if_none_match: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct AccessLogEntry {
+    pub time: DateTime<Utc>,
+    pub peer: String,
+    pub method: String,
+    pub path: String,
+    pub status: u16,
+    pub bytes: u64,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ServerStatus {
+    pub running: bool,
+    pub address: String,
+    pub log: Vec<AccessLogEntry>,
+}
+
+async fn get_address() -> String {
+    userdata::get_settings()
+        .await
+        .server_address
+        .clone()
+        .unwrap_or(String::from(DEFAULT_ADDRESS))
+}
+
+/// Serves the loaded package index and the cached archives with Thunderstore's API layout, so
+/// other managers can use this one as a mirror.
+#[tauri::command]
+pub async fn start_server(address: Option<String>) -> Result<String, String> {
+    let mut server = SERVER.lock().await;
+    if server.is_some() {
+        return Err(String::from("The server is already running"));
+    }
+
+    if let Some(address) = address {
+        userdata::get_settings().await.server_address = Some(address);
+        userdata::save_data().await;
+    }
+
+    let address = get_address().await;
+    let listener = TcpListener::bind(&address)
+        .await
+        .map_err(|e| format!("Failed to bind {address}: {e}"))?;
+    let (shutdown, mut shutdown_rx) = watch::channel(false);
+
+    tauri::async_runtime::spawn(async move {
+        loop {
+            let shutdown = Box::pin(shutdown_rx.changed());
+            let accept = Box::pin(listener.accept());
+            match future::select(shutdown, accept).await {
+                Either::Left(_) => break,
+                Either::Right((accepted, _)) => {
+                    if let Ok((stream, peer)) = accepted {
+                        tauri::async_runtime::spawn(handle_connection(stream, peer));
+                    }
+                }
+            }
+        }
+    });
+
+    println!("Serving package on {address}");
+    *server = Some(RunningServer { address: address.clone(), shutdown });
+    Ok(address)
+}
+
+#[tauri::command]
+pub async fn stop_server() {
+    if let Some(server) = SERVER.lock().await.take() {
+        let _ = server.shutdown.send(true);
+    }
+}
+
+#[tauri::command]
+pub async fn get_server_status() -> ServerStatus {
+    let server = SERVER.lock().await;
+    let address = match &*server {
+        Some(s) => s.address.clone(),
+        None => get_address().await,
+    };
+
+    ServerStatus {
+        running: server.is_some(),
+        address,
+        log: ACCESS_LOG.lock().await.clone(),
+    }
+}
+
+async fn log_access(entry: AccessLogEntry) {
+    let log_file = format!("{}\\server.log", userdata::get_app_dir());
+    if let Ok(mut f) = OpenOptions::new().create(true).append(true).open(&log_file) {
+        let _ = writeln!(
+            f,
+            "{} {} \"{} {}\" {} {}",
+            entry.time.to_rfc3339(),
+            entry.peer,
+            entry.method,
+            entry.path,
+            entry.status,
+            entry.bytes
+        );
+    }
+
+    let mut log = ACCESS_LOG.lock().await;
+    log.push(entry);
+    if log.len() > MAX_LOG_ENTRIES {
+        let excess = log.len() - MAX_LOG_ENTRIES;
+        log.drain(..excess);
+    }
+}
+
+async fn handle_connection(mut stream: TcpStream, peer: SocketAddr) {
+    let request = match tokio::time::timeout(Duration::from_secs(10), read_request(&mut stream)).await {
+        Ok(Some(request)) => request,
+        _ => return,
+    };
+
+    let (status, bytes) = respond(&mut stream, &request).await.unwrap_or((500, 0));
+    let _ = stream.shutdown().await;
+
+    log_access(AccessLogEntry {
+        time: Utc::now(),
+        peer: peer.to_string(),
+        method: request.method,
+        path: request.path,
+        status,
+        bytes,
+    })
+    .await;
+}
+
+async fn read_request(stream: &mut TcpStream) -> Option<Request> {
+    let mut buf = vec![];
+    let mut chunk = [0u8; 1024];
+
+    // Only the head matters, requests to this server have no body
+    while !buf.windows(4).any(|w| w == b"\r\n\r\n") {
+        let n = stream.read(&mut chunk).await.ok()?;
+        if n == 0 || buf.len() + n > MAX_REQUEST_SIZE {
+            return None;
+        }
+        buf.extend_from_slice(&chunk[..n]);
+    }
+
+    let head = String::from_utf8_lossy(&buf).into_owned();
+    let mut lines = head.lines();
+    let mut request_line = lines.next()?.split_whitespace();
+    let method = request_line.next()?.to_owned();
+    let path = request_line.next()?.to_owned();
+
+    let mut host = None;
+    let mut if_none_match = None;
+    for line in lines {
+        if let Some((name, value)) = line.split_once(':') {
+            match name.trim().to_lowercase().as_str() {
+                "host" => host = Some(value.trim().to_owned()),
+                "if-none-match" => if_none_match = Some(value.trim().to_owned()),
+                _ => {}
+            }
+        }
+    }
+
+    Some(Request { method, path, host, if_none_match })
+}
+
+async fn write_head(stream: &mut TcpStream, status: u16, headers: &[(&str, String)]) -> std::io::Result<()> {
+    let reason = match status {
+        200 => "OK",
+        304 => "Not Modified",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        _ => "Internal Server Error",
+    };
+
+    let mut head = format!("HTTP/1.1 {status} {reason}\r\n");
+    for (name, value) in headers {
+        head.push_str(&format!("{name}: {value}\r\n"));
+    }
+    head.push_str("Connection: close\r\n\r\n");
+
+    stream.write_all(head.as_bytes()).await
+}
+
+async fn write_text(stream: &mut TcpStream, status: u16, text: &str) -> std::io::Result<(u16, u64)> {
+    write_head(stream, status, &[
+        ("Content-Type", String::from("text/plain")),
+        ("Content-Length", text.len().to_string()),
+    ]).await?;
+    stream.write_all(text.as_bytes()).await?;
+    Ok((status, text.len() as u64))
+}
+
+async fn respond(stream: &mut TcpStream, request: &Request) -> std::io::Result<(u16, u64)> {
+    if request.method != "GET" && request.method != "HEAD" {
+        return write_text(stream, 405, "Method not allowed").await;
+    }
+
+    let path = request.path.split('?').next().unwrap_or_default();
+    if path.trim_end_matches('/') == "/api/v1/package" {
+        serve_index(stream, request).await
+    } else if let Some(version_path) = path.strip_prefix("/package/download/") {
+        serve_archive(stream, request, version_path.trim_end_matches('/')).await
+    } else {
+        write_text(stream, 404, "Not found").await
+    }
+}
+
+/// The package as mirrors see it. Only versions this server has the archive of are listed, and
+/// everything is downloaded through it, private mods included.
+fn build_index(package: &Package, cached: &HashSet<String>, host: &str) -> Vec<Mod> {
+    package
+        .mods
+        .values()
+        .filter_map(|m| {
+            let mut m = m.clone();
+            m.versions.retain(|v| match v.origin {
+                Origin::Public => cached.contains(&v.full_name),
+                Origin::Private => Path::new(&v.download_url).exists(),
+            });
+            if m.versions.is_empty() {
+                return None;
+            }
+
+            m.origin = Origin::Public;
+            for v in &mut m.versions {
+                v.download_url = format!(
+                    "http://{host}/package/download/{}/{}/{}/",
+                    m.owner, m.name, v.version_number
+                );
+                v.origin = Origin::Public;
+            }
+            Some(m)
+        })
+        .collect()
+}
+
+async fn serve_index(stream: &mut TcpStream, request: &Request) -> std::io::Result<(u16, u64)> {
+    let host = match &request.host {
+        Some(host) => host.clone(),
+        None => stream.local_addr()?.to_string(),
+    };
+
+    // Rebuilt only once the package or the cached archives change
+    let package = thunderstore::get_package().await;
+    let archives = archives::get_generation();
+    let (etag, body) = {
+        let mut served = SERVED_INDEXES.lock().await;
+        let current = served
+            .get(&host)
+            .filter(|s| s.archives == archives && s.package.ptr_eq(&Arc::downgrade(&package)));
+
+        match current {
+            Some(index) => (index.etag.clone(), index.body.clone()),
+            None => {
+                let cached = archives::get_cached_versions().await;
+                let (index_package, index_host) = (package.clone(), host.clone());
+                let body = tauri::async_runtime::spawn_blocking(move || {
+                    serde_json::to_vec(&build_index(&index_package, &cached, &index_host)).unwrap()
+                })
+                .await
+                .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e.to_string()))?;
+                let etag = {
+                    let mut hasher = DefaultHasher::new();
+                    body.hash(&mut hasher);
+                    format!("\"{:x}\"", hasher.finish())
+                };
+
+                let index = ServedIndex {
+                    package: Arc::downgrade(&package),
+                    archives,
+                    etag: etag.clone(),
+                    body: Arc::new(body),
+                };
+                let body = index.body.clone();
+                served.insert(host, index);
+                (etag, body)
+            }
+        }
+    };
+
+    if request.if_none_match.as_deref() == Some(etag.as_str()) {
+        write_head(stream, 304, &[("ETag", etag)]).await?;
+        return Ok((304, 0));
+    }
+
+    write_head(stream, 200, &[
+        ("Content-Type", String::from("application/json")),
+        ("Content-Length", body.len().to_string()),
+        ("ETag", etag),
+    ]).await?;
+
+    if request.method == "HEAD" {
+        return Ok((200, 0));
+    }
+    stream.write_all(&body).await?;
+    Ok((200, body.len() as u64))
+}
+
+async fn serve_archive(stream: &mut TcpStream, request: &Request, version_path: &str) -> std::io::Result<(u16, u64)> {
+    let parts: Vec<&str> = version_path.split('/').collect();
+    let [owner, name, version_number] = parts[..] else {
+        return write_text(stream, 404, "Not found").await;
+    };
+
+    let version = thunderstore::get_mod(&format!("{owner}-{name}"))
+        .await
+        .and_then(|m| {
+            let version_number = ModVersion::parse(version_number)?;
+            m.versions
+                .into_iter()
+                .find(|v| ModVersion::parse(&v.version_number).as_ref() == Some(&version_number))
+        });
+    let Some(version) = version else {
+        return write_text(stream, 404, "Unknown package version").await;
+    };
+
+    let archive_path = match version.origin {
+        Origin::Public => archives::find(&version).await,
+        Origin::Private => Some(version.download_url.clone()).filter(|p| Path::new(p).exists()),
+    };
+    let Some(archive_path) = archive_path else {
+        return write_text(stream, 404, "Package version isn't cached").await;
+    };
+
+    let mut file = tokio::fs::File::open(&archive_path).await?;
+    let size = file.metadata().await?.len();
+
+    write_head(stream, 200, &[
+        ("Content-Type", String::from("application/zip")),
+        ("Content-Length", size.to_string()),
+        ("Content-Disposition", format!("attachment; filename=\"{}.zip\"", version.full_name)),
+    ]).await?;
+
+    if request.method == "HEAD" {
+        return Ok((200, 0));
+    }
+    let bytes = tokio::io::copy(&mut file, stream).await?;
+    Ok((200, bytes))
+}
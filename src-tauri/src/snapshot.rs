@@ -0,0 +1,221 @@
+use std::{
+    collections::HashSet,
+    fs::{File, OpenOptions},
+    io::{Read, Write},
+    path::Path,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+
+use crate::{userdata, utils};
+
+static SNAPSHOT_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+#[derive(Serialize, Deserialize)]
+struct JournalEntry {
+    path: String,
+    /// Copy of what was at `path`, `None` if there was nothing
+    backup: Option<String>,
+}
+
+/// Journal of the files an operation is about to change, so they can be put back if it fails.
+/// Entries are appended to disk as they're added, so a snapshot can also be restored after a crash.
+pub struct Snapshot {
+    dir: String,
+    entries: Vec<JournalEntry>,
+    saved: HashSet<String>,
+}
+
+pub fn get_snapshots_dir() -> String {
+    format!("{}\\snapshots", userdata::get_cache_dir())
+}
+
+impl Snapshot {
+    pub fn new() -> Result<Snapshot, String> {
+        let id = format!(
+            "{}-{}",
+            Utc::now().format("%Y%m%d%H%M%S%3f"),
+            SNAPSHOT_COUNTER.fetch_add(1, Ordering::Relaxed)
+        );
+        Snapshot::create(format!("{}\\{id}", get_snapshots_dir()))
+    }
+
+    fn create(dir: String) -> Result<Snapshot, String> {
+        std::fs::create_dir_all(&dir).map_err(|e| format!("Failed to create snapshot: {e}"))?;
+        File::create(format!("{dir}\\journal.json")).map_err(|e| format!("Failed to write snapshot journal: {e}"))?;
+        Ok(Snapshot { dir, entries: vec![], saved: HashSet::new() })
+    }
+
+    /// Opens a snapshot left behind by an operation that never finished
+    pub fn open(dir: &str) -> Result<Snapshot, String> {
+        let mut buf = String::new();
+        File::open(format!("{dir}\\journal.json"))
+            .and_then(|mut f| f.read_to_string(&mut buf))
+            .map_err(|e| format!("Failed to read snapshot journal: {e}"))?;
+
+        // One entry per line. A line cut short by a crash was never acted on, the path it's
+        // about hadn't been touched yet.
+        let entries: Vec<JournalEntry> = buf
+            .lines()
+            .map_while(|line| serde_json::from_str(line).ok())
+            .collect();
+        Ok(Snapshot {
+            dir: dir.to_owned(),
+            saved: entries.iter().map(|e| e.path.clone()).collect(),
+            entries,
+        })
+    }
+
+    pub fn dir(&self) -> &str {
+        &self.dir
+    }
+
+    fn append_entry(&self, entry: &JournalEntry) -> Result<(), String> {
+        OpenOptions::new()
+            .append(true)
+            .open(format!("{}\\journal.json", self.dir))
+            .and_then(|mut f| f.write_all(format!("{}\n", serde_json::to_string(entry).unwrap()).as_bytes()))
+            .map_err(|e| format!("Failed to write snapshot journal: {e}"))
+    }
+
+    /// Keeps a copy of the file or folder at `path` as it is now. Paths that don't exist yet are
+    /// removed on restore, along with the folders created for them.
+    pub fn save(&mut self, path: &str) -> Result<(), String> {
+        if self.saved.contains(path) {
+            return Ok(()); // Only the first state matters
+        }
+
+        let missing_parent = Path::new(path)
+            .ancestors()
+            .skip(1)
+            .take_while(|p| !p.as_os_str().is_empty() && !p.exists())
+            .last()
+            .map(|p| p.display().to_string());
+        if let Some(parent) = missing_parent {
+            self.save(&parent)?;
+        }
+
+        let backup = if Path::new(path).exists() {
+            let backup = format!("{}\\{}", self.dir, self.entries.len());
+            let copied = if Path::new(path).is_dir() {
+                utils::copy_dir(path, &backup)
+            } else {
+                std::fs::copy(path, &backup).map(|_| ())
+            };
+            copied.map_err(|e| format!("Failed to back up {path}: {e}"))?;
+            Some(backup)
+        } else {
+            None
+        };
+
+        let entry = JournalEntry { path: path.to_owned(), backup };
+        self.append_entry(&entry)?;
+        self.saved.insert(entry.path.clone());
+        self.entries.push(entry);
+        Ok(())
+    }
+
+    /// Puts every saved path back the way it was and deletes the snapshot
+    pub fn restore(self) -> Result<(), String> {
+        let mut errors = vec![];
+
+        for entry in self.entries.iter().rev() {
+            let restored = utils::remove_path(&entry.path).and_then(|_| match &entry.backup {
+                Some(backup) if Path::new(backup).is_dir() => utils::copy_dir(backup, &entry.path),
+                Some(backup) => {
+                    if let Some(parent) = Path::new(&entry.path).parent() {
+                        std::fs::create_dir_all(parent)?;
+                    }
+                    std::fs::copy(backup, &entry.path).map(|_| ())
+                }
+                None => Ok(()),
+            });
+
+            if let Err(e) = restored {
+                errors.push(format!("{}: {e}", entry.path));
+            }
+        }
+
+        if errors.is_empty() {
+            self.discard();
+            Ok(())
+        } else {
+            // Keep the backups around, they're the only copy left
+            Err(format!("Failed to restore:\n{}", errors.join("\n")))
+        }
+    }
+
+    /// Deletes the snapshot, once the operation it covered went through
+    pub fn discard(self) {
+        let _ = std::fs::remove_dir_all(&self.dir);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Folder for a test to work in, with a snapshot in it
+    fn setup(name: &str) -> (String, Snapshot) {
+        let root = std::env::temp_dir().join(format!("snapshot-test-{name}-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(&root).unwrap();
+        let root = root.display().to_string();
+        let snapshot = Snapshot::create(format!("{root}/snapshot")).unwrap();
+        (root, snapshot)
+    }
+
+    #[test]
+    fn restores_changed_and_new_files() {
+        let (root, mut snapshot) = setup("restore");
+        let changed = format!("{root}/changed.txt");
+        let created = format!("{root}/new/created.txt");
+        std::fs::write(&changed, "before").unwrap();
+
+        snapshot.save(&changed).unwrap();
+        snapshot.save(&created).unwrap();
+        std::fs::write(&changed, "after").unwrap();
+        std::fs::create_dir_all(format!("{root}/new")).unwrap();
+        std::fs::write(&created, "new").unwrap();
+
+        snapshot.restore().unwrap();
+        assert_eq!(std::fs::read_to_string(&changed).unwrap(), "before");
+        assert!(!Path::new(&format!("{root}/new")).exists());
+        let _ = std::fs::remove_dir_all(&root);
+    }
+
+    #[test]
+    fn journal_is_appended_once_per_path() {
+        let (root, mut snapshot) = setup("journal");
+        let file = format!("{root}/file.txt");
+        std::fs::write(&file, "before").unwrap();
+
+        snapshot.save(&file).unwrap();
+        std::fs::write(&file, "after").unwrap();
+        snapshot.save(&file).unwrap();
+
+        let opened = Snapshot::open(snapshot.dir()).unwrap();
+        assert_eq!(opened.entries.len(), 1);
+        opened.restore().unwrap();
+        assert_eq!(std::fs::read_to_string(&file).unwrap(), "before");
+        let _ = std::fs::remove_dir_all(&root);
+    }
+
+    #[test]
+    fn open_skips_an_entry_cut_short() {
+        let (root, mut snapshot) = setup("truncated");
+        let file = format!("{root}/file.txt");
+        snapshot.save(&file).unwrap();
+
+        let journal = format!("{}\\journal.json", snapshot.dir());
+        let mut f = OpenOptions::new().append(true).open(&journal).unwrap();
+        f.write_all(br#"{"path":"#).unwrap();
+
+        let opened = Snapshot::open(snapshot.dir()).unwrap();
+        assert_eq!(opened.entries.len(), 1);
+        assert_eq!(opened.entries[0].path, file);
+        let _ = std::fs::remove_dir_all(&root);
+    }
+}
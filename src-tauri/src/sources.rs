@@ -0,0 +1,471 @@
+use std::{collections::HashMap, fs::{File, OpenOptions}, io::{Read, Write}, path::Path, time::Duration};
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use futures_util::StreamExt;
+use reqwest::{header, StatusCode};
+use serde::{Deserialize, Serialize};
+
+use crate::{profiles::Manifest, thunderstore::{self, Mod, Origin, PackageCacheInfo, Version}, userdata};
+
+const THUNDERSTORE_PACKAGE_URL: &str = "https://thunderstore.io/c/lethal-company/api/v1/package/";
+
+const DOWNLOAD_ATTEMPTS: u32 = 5;
+/// Waited before the first retry, doubled for every next one
+const DOWNLOAD_RETRY_DELAY: Duration = Duration::from_secs(1);
+/// Downloads that receive nothing for this long are retried
+const DOWNLOAD_STALL_TIMEOUT: Duration = Duration::from_secs(30);
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(tag = "type")]
+pub enum SourceConfig {
+    Thunderstore,
+    Mirror { base_url: String },
+    Local { path: String },
+}
+
+pub enum IndexResponse {
+    NotModified,
+    Updated {
+        data: String,
+        etag: Option<String>,
+        last_modified: Option<String>,
+    },
+}
+
+/// Called with the amount of bytes of an archive fetched so far
+pub type ProgressFn<'a> = &'a (dyn Fn(u64) + Send + Sync);
+
+/// Where the package index and the mod archives come from.
+#[async_trait]
+pub trait PackageSource: Send + Sync {
+    /// Whether the source needs network access, remote sources are skipped in offline mode
+    fn is_remote(&self) -> bool;
+
+    /// Fetches the raw package index, in the same format as Thunderstore's `/api/v1/package/`.
+    /// `cache` holds the validators of the cached index, if there's one for this source.
+    async fn fetch_index(&self, cache: Option<&PackageCacheInfo>) -> Result<IndexResponse, String>;
+
+    /// Stores the archive of `version` at `dest`, reporting the bytes written so far.
+    async fn fetch_archive(&self, version: &Version, dest: &str, on_progress: ProgressFn<'_>) -> Result<(), String>;
+}
+
+pub struct ThunderstoreSource;
+
+/// A server exposing Thunderstore's API under another base URL, like a LAN mirror.
+pub struct MirrorSource {
+    pub base_url: String,
+}
+
+/// A folder of Thunderstore-format zips, named `Owner-Name-Version.zip`.
+pub struct LocalSource {
+    pub path: String,
+}
+
+struct LocalArchive {
+    owner: String,
+    manifest: Manifest,
+    icon: Option<String>,
+    readme: Option<String>,
+}
+
+impl SourceConfig {
+    /// Identifies the source, a cached index is only used with the source it came from
+    pub fn id(&self) -> String {
+        match self {
+            SourceConfig::Thunderstore => String::from("thunderstore"),
+            SourceConfig::Mirror { base_url } => format!("mirror:{base_url}"),
+            SourceConfig::Local { path } => format!("local:{path}"),
+        }
+    }
+
+    pub fn build(&self) -> Box<dyn PackageSource> {
+        match self {
+            SourceConfig::Thunderstore => Box::new(ThunderstoreSource),
+            SourceConfig::Mirror { base_url } => Box::new(MirrorSource {
+                base_url: base_url.trim_end_matches('/').to_owned(),
+            }),
+            SourceConfig::Local { path } => Box::new(LocalSource { path: path.clone() }),
+        }
+    }
+}
+
+pub async fn get_source_config() -> SourceConfig {
+    userdata::get_settings()
+        .await
+        .package_source
+        .clone()
+        .unwrap_or(SourceConfig::Thunderstore)
+}
+
+pub async fn get_source() -> Box<dyn PackageSource> {
+    get_source_config().await.build()
+}
+
+/// The source able to provide the archive of `version`
+pub async fn get_version_source(version: &Version) -> Box<dyn PackageSource> {
+    match version.origin {
+        Origin::Public => get_source().await,
+        Origin::Private => Box::new(LocalSource {
+            path: userdata::get_settings().await.private_repository.clone().unwrap_or_default(),
+        }),
+    }
+}
+
+/// Package entries built from the private repository, if one is set
+pub async fn get_private_mods() -> Vec<Mod> {
+    let Some(path) = userdata::get_settings().await.private_repository.clone() else {
+        return vec![];
+    };
+
+    let mut mods = LocalSource { path }.scan();
+    for m in &mut mods {
+        m.origin = Origin::Private;
+        for v in &mut m.versions {
+            v.origin = Origin::Private;
+        }
+    }
+    mods
+}
+
+#[tauri::command]
+pub async fn get_package_source() -> SourceConfig {
+    get_source_config().await
+}
+
+#[tauri::command]
+pub async fn set_package_source(source: SourceConfig) -> Result<(), String> {
+    userdata::get_settings().await.package_source = Some(source);
+    userdata::save_data().await;
+
+    thunderstore::load_package().await
+}
+
+#[tauri::command]
+pub async fn get_private_repository() -> Option<String> {
+    userdata::get_settings().await.private_repository.clone()
+}
+
+#[tauri::command]
+pub async fn set_private_repository(path: Option<String>) -> Result<(), String> {
+    if let Some(path) = &path {
+        if !Path::new(path).is_dir() {
+            return Err(format!("{path} is not a folder"));
+        }
+    }
+
+    userdata::get_settings().await.private_repository = path;
+    userdata::save_data().await;
+
+    // The repository is set either way, the public index failing to load isn't about it
+    let _ = thunderstore::load_package().await;
+    Ok(())
+}
+
+async fn fetch_index_http(url: &str, cache: Option<&PackageCacheInfo>) -> Result<IndexResponse, String> {
+    let mut request = reqwest::Client::new().get(url);
+
+    if let Some(cache) = cache {
+        if let Some(etag) = &cache.etag {
+            request = request.header(header::IF_NONE_MATCH, etag);
+        }
+        if let Some(last_modified) = &cache.last_modified {
+            request = request.header(header::IF_MODIFIED_SINCE, last_modified);
+        }
+    }
+
+    let res = request.send().await.map_err(|e| e.to_string())?;
+
+    if res.status() == StatusCode::NOT_MODIFIED {
+        return Ok(IndexResponse::NotModified);
+    }
+    if !res.status().is_success() {
+        return Err(format!("{url} responded with {}", res.status()));
+    }
+
+    let (etag, last_modified) = {
+        let get_header = |name: header::HeaderName| {
+            res.headers()
+                .get(name)
+                .and_then(|v| v.to_str().ok())
+                .map(String::from)
+        };
+        (get_header(header::ETAG), get_header(header::LAST_MODIFIED))
+    };
+
+    let data = res.text().await.map_err(|e| e.to_string())?;
+    Ok(IndexResponse::Updated { data, etag, last_modified })
+}
+
+enum AttemptError {
+    /// Worth trying again, like a dropped connection or a busy server
+    Transient(String),
+    Fatal(String),
+}
+
+/// Downloads into `partial`, continuing where a previous attempt left off
+async fn fetch_archive_attempt(url: &str, partial: &str, on_progress: ProgressFn<'_>) -> Result<(), AttemptError> {
+    let existing = std::fs::metadata(partial).map(|m| m.len()).unwrap_or(0);
+
+    let mut request = reqwest::Client::new().get(url);
+    if existing > 0 {
+        request = request.header(header::RANGE, format!("bytes={existing}-"));
+    }
+    let res = request.send().await.map_err(|e| AttemptError::Transient(e.to_string()))?;
+
+    let status = res.status();
+    let resumed = match status {
+        StatusCode::PARTIAL_CONTENT => true,
+        // Everything is already there
+        StatusCode::RANGE_NOT_SATISFIABLE if existing > 0 => return Ok(()),
+        s if s.is_success() => false,
+        s if s.is_server_error() || s == StatusCode::TOO_MANY_REQUESTS || s == StatusCode::REQUEST_TIMEOUT => {
+            return Err(AttemptError::Transient(format!("{url} responded with {s}")));
+        }
+        s => return Err(AttemptError::Fatal(format!("{url} responded with {s}"))),
+    };
+
+    // Servers that ignore the range send the whole file again
+    let mut file = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .append(resumed)
+        .truncate(!resumed)
+        .open(partial)
+        .map_err(|e| AttemptError::Fatal(e.to_string()))?;
+    let mut stream = res.bytes_stream();
+    let mut written = if resumed { existing } else { 0 };
+    on_progress(written);
+
+    loop {
+        let chunk = match tokio::time::timeout(DOWNLOAD_STALL_TIMEOUT, stream.next()).await {
+            Ok(Some(chunk)) => chunk.map_err(|e| AttemptError::Transient(e.to_string()))?,
+            Ok(None) => break,
+            Err(_) => return Err(AttemptError::Transient(String::from("The download stalled"))),
+        };
+        file.write_all(&chunk).map_err(|e| AttemptError::Fatal(e.to_string()))?;
+        written += chunk.len() as u64;
+        on_progress(written);
+    }
+
+    Ok(())
+}
+
+/// Downloads `url` to `dest`. The file is written next to it with a `.partial` extension and
+/// only moved to `dest` once it has `expected_size` bytes, so `dest` is never half-written.
+async fn fetch_archive_http(url: &str, dest: &str, expected_size: u64, on_progress: ProgressFn<'_>) -> Result<(), String> {
+    let partial = format!("{dest}.partial");
+    let mut attempt = 0;
+
+    loop {
+        let error = match fetch_archive_attempt(url, &partial, on_progress).await {
+            Ok(()) => {
+                let size = std::fs::metadata(&partial).map(|m| m.len()).unwrap_or(0);
+                if expected_size == 0 || size == expected_size {
+                    return std::fs::rename(&partial, dest).map_err(|e| e.to_string());
+                }
+
+                // A short file can still be resumed, anything else has to start over
+                if size > expected_size {
+                    let _ = std::fs::remove_file(&partial);
+                }
+                AttemptError::Transient(format!("Expected {expected_size} bytes but got {size}"))
+            }
+            Err(e) => e,
+        };
+
+        attempt += 1;
+        match error {
+            AttemptError::Transient(e) if attempt < DOWNLOAD_ATTEMPTS => {
+                let delay = DOWNLOAD_RETRY_DELAY * 2u32.pow(attempt - 1);
+                println!("Download of {url} failed ({e}), retrying in {}s", delay.as_secs());
+                tokio::time::sleep(delay).await;
+            }
+            AttemptError::Transient(e) | AttemptError::Fatal(e) => return Err(e),
+        }
+    }
+}
+
+#[async_trait]
+impl PackageSource for ThunderstoreSource {
+    fn is_remote(&self) -> bool {
+        true
+    }
+
+    async fn fetch_index(&self, cache: Option<&PackageCacheInfo>) -> Result<IndexResponse, String> {
+        fetch_index_http(THUNDERSTORE_PACKAGE_URL, cache).await
+    }
+
+    async fn fetch_archive(&self, version: &Version, dest: &str, on_progress: ProgressFn<'_>) -> Result<(), String> {
+        fetch_archive_http(&version.download_url, dest, version.file_size.max(0) as u64, on_progress).await
+    }
+}
+
+impl MirrorSource {
+    /// Points a Thunderstore download URL to the mirror
+    fn rebase_url(&self, url: &str) -> String {
+        match url.find("/package/download/") {
+            Some(i) => format!("{}{}", self.base_url, &url[i..]),
+            None => url.to_owned(),
+        }
+    }
+}
+
+#[async_trait]
+impl PackageSource for MirrorSource {
+    fn is_remote(&self) -> bool {
+        true
+    }
+
+    async fn fetch_index(&self, cache: Option<&PackageCacheInfo>) -> Result<IndexResponse, String> {
+        fetch_index_http(&format!("{}/api/v1/package/", self.base_url), cache).await
+    }
+
+    async fn fetch_archive(&self, version: &Version, dest: &str, on_progress: ProgressFn<'_>) -> Result<(), String> {
+        fetch_archive_http(&self.rebase_url(&version.download_url), dest, version.file_size.max(0) as u64, on_progress).await
+    }
+}
+
+impl LocalSource {
+    fn read_archive(path: &Path) -> Result<LocalArchive, String> {
+        let zip_file = File::open(path).map_err(|e| e.to_string())?;
+        let mut archive = zip::ZipArchive::new(zip_file).map_err(|e| e.to_string())?;
+
+        let mut read_entry = |name: &str| -> Option<Vec<u8>> {
+            let mut buf = vec![];
+            archive.by_name(name).ok()?.read_to_end(&mut buf).ok()?;
+            Some(buf)
+        };
+
+        let manifest_buf = read_entry("manifest.json").ok_or("Archive has no manifest.json")?;
+        let manifest_str = String::from_utf8_lossy(&manifest_buf);
+        let manifest = serde_json::from_str::<Manifest>(manifest_str.trim_start_matches("\u{feff}"))
+            .map_err(|e| e.to_string())?;
+
+        // Thunderstore names archives Owner-Name-Version, which is the only place the owner is found
+        let name = manifest.name.clone().ok_or("manifest.json has no name")?;
+        let stem = path.file_stem().unwrap().to_string_lossy();
+        let suffix = format!("-{}-{}", name, manifest.version_number);
+        let owner = match stem.strip_suffix(&suffix) {
+            Some(owner) if !owner.is_empty() => owner.to_owned(),
+            _ => String::from("Local"),
+        };
+
+        let readme = read_entry("README.md").map(|b| String::from_utf8_lossy(&b).into_owned());
+
+        // The frontend shows icons straight from the package, so they're handed over as data URLs
+        let icon = read_entry("icon.png").and_then(|image| {
+            let icons_dir = format!("{}\\icons", userdata::get_cache_dir());
+            let icon_path = format!("{icons_dir}\\{owner}-{name}-{}.png", manifest.version_number);
+
+            std::fs::create_dir_all(&icons_dir).ok()?;
+            std::fs::write(&icon_path, image).ok()?;
+            Some(image_base64::to_base64(&icon_path))
+        });
+
+        Ok(LocalArchive { owner, manifest, icon, readme })
+    }
+
+    /// Builds package entries out of every archive in the folder
+    pub fn scan(&self) -> Vec<Mod> {
+        let mut mods: HashMap<String, Mod> = HashMap::new();
+
+        let Ok(entries) = std::fs::read_dir(&self.path) else {
+            return vec![];
+        };
+
+        for entry in entries.flatten() {
+            let path = entry.path();
+            if !path.extension().map_or(false, |e| e.eq_ignore_ascii_case("zip")) {
+                continue;
+            }
+
+            let LocalArchive { owner, manifest, icon, readme } = match Self::read_archive(&path) {
+                Ok(a) => a,
+                Err(e) => {
+                    println!("Skipping {}: {e}", path.display());
+                    continue;
+                }
+            };
+
+            let name = manifest.name.unwrap();
+            let full_name = format!("{owner}-{name}");
+            let metadata = entry.metadata().ok();
+            let date: DateTime<Utc> = metadata
+                .as_ref()
+                .and_then(|m| m.modified().ok())
+                .map(DateTime::from)
+                .unwrap_or_else(Utc::now);
+
+            let version = Version {
+                date_created: date,
+                dependencies: manifest.dependencies,
+                description: manifest.description,
+                download_url: path.display().to_string(),
+                downloads: 0,
+                file_size: metadata.map_or(0, |m| m.len() as i64),
+                full_name: format!("{full_name}-{}", manifest.version_number),
+                icon: icon.unwrap_or_default(),
+                is_active: true,
+                name: name.clone(),
+                uuid4: String::new(),
+                version_number: manifest.version_number,
+                website_url: manifest.website_url,
+                readme,
+                origin: Origin::Public,
+            };
+
+            let _mod = mods.entry(full_name.clone()).or_insert_with(|| Mod {
+                categories: vec![String::from("Mods")],
+                date_created: date,
+                date_updated: date,
+                full_name: full_name.clone(),
+                has_nsfw_content: false,
+                is_deprecated: false,
+                is_pinned: false,
+                name,
+                owner,
+                package_url: String::new(),
+                rating_score: 0,
+                uuid4: full_name,
+                versions: vec![],
+                origin: Origin::Public,
+            });
+
+            _mod.date_created = _mod.date_created.min(date);
+            _mod.date_updated = _mod.date_updated.max(date);
+            _mod.versions.push(version);
+        }
+
+        let mut mods: Vec<Mod> = mods.into_values().collect();
+        for m in &mut mods {
+            thunderstore::sort_versions(&mut m.versions);
+        }
+        mods
+    }
+}
+
+#[async_trait]
+impl PackageSource for LocalSource {
+    fn is_remote(&self) -> bool {
+        false
+    }
+
+    async fn fetch_index(&self, _cache: Option<&PackageCacheInfo>) -> Result<IndexResponse, String> {
+        if !Path::new(&self.path).exists() {
+            return Err(format!("{} doesn't exist", self.path));
+        }
+
+        let data = serde_json::to_string(&self.scan()).map_err(|e| e.to_string())?;
+        Ok(IndexResponse::Updated { data, etag: None, last_modified: None })
+    }
+
+    async fn fetch_archive(&self, version: &Version, dest: &str, on_progress: ProgressFn<'_>) -> Result<(), String> {
+        // Copied under another name first, like downloads, so `dest` is never half-written
+        let partial = format!("{dest}.partial");
+        std::fs::copy(&version.download_url, &partial)
+            .map(on_progress)
+            .and_then(|_| std::fs::rename(&partial, dest))
+            .map_err(|e| format!("Failed to copy {}: {e}", version.download_url))
+    }
+}
@@ -0,0 +1,182 @@
+use std::{
+    collections::HashMap,
+    fs::{File, OpenOptions},
+    io::{Read, Write},
+    path::Path,
+    sync::{Arc, Mutex},
+};
+
+use once_cell::sync::Lazy;
+
+use crate::{snapshot::Snapshot, thunderstore::Version, userdata, utils};
+
+/// One lock per version folder, held while it's extracted so two installs never extract it at once
+static FOLDER_LOCKS: Lazy<Mutex<HashMap<String, Arc<tokio::sync::Mutex<()>>>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// Extensions of files mods and the game may write to, which profiles never share
+static CONFIG_EXTENSIONS: [&str; 6] = ["cfg", "ini", "json", "yml", "yaml", "xml"];
+
+pub fn get_store_dir() -> String {
+    format!("{}\\store", userdata::get_app_dir())
+}
+
+/// Folder a version is extracted to, laid out like a profile folder
+pub fn get_version_folder(version: &Version) -> String {
+    format!("{}\\{}", get_store_dir(), version.full_name)
+}
+
+pub async fn is_store_mode() -> bool {
+    userdata::get_settings().await.store_mode.unwrap_or(false)
+}
+
+#[tauri::command]
+pub async fn get_store_mode() -> bool {
+    is_store_mode().await
+}
+
+/// With store mode, versions are extracted once and profiles get hardlinks to their files
+#[tauri::command]
+pub async fn set_store_mode(enabled: bool) {
+    userdata::get_settings().await.store_mode = Some(enabled);
+    userdata::save_data().await;
+}
+
+/// Runs `extract` into the store folder of `version`, unless it's already there. Returns the folder.
+/// The extraction runs on a blocking thread, other versions can be extracted meanwhile.
+pub async fn get_or_extract(version: &Version, extract: impl FnOnce(&str) -> Result<(), String> + Send + 'static) -> Result<String, String> {
+    let folder = get_version_folder(version);
+    let lock = FOLDER_LOCKS.lock().unwrap().entry(folder.clone()).or_default().clone();
+    // Owned, so it's kept by the extraction even if the job waiting on it is dropped
+    let guard = lock.lock_owned().await;
+    if Path::new(&folder).exists() {
+        return Ok(folder);
+    }
+
+    let full_name = version.full_name.clone();
+    tauri::async_runtime::spawn_blocking(move || {
+        let _guard = guard;
+        extract_into(&folder, &full_name, extract).map(|_| folder)
+    })
+    .await
+    .map_err(|e| e.to_string())?
+}
+
+fn extract_into(folder: &str, full_name: &str, extract: impl FnOnce(&str) -> Result<(), String>) -> Result<(), String> {
+    // Extract next to it first, a version that's in the store is always complete
+    let temp_folder = format!("{folder}.tmp");
+    utils::remove_path(&temp_folder).map_err(|e| format!("Failed to remove {temp_folder}: {e}"))?;
+    std::fs::create_dir_all(&temp_folder).map_err(|e| format!("Failed to create {temp_folder}: {e}"))?;
+
+    // External files were recorded under the temporary folder
+    let extracted = extract(&temp_folder).and_then(|_| {
+        let mut files = vec![];
+        collect_files(&temp_folder, "", &mut files).map_err(|e| format!("Failed to read {temp_folder}: {e}"))?;
+        for relative_path in files.iter().filter(|f| f.ends_with("\\external_files.json")) {
+            let path = format!("{temp_folder}\\{relative_path}");
+            rebase_external_files(&path, &path, &temp_folder, folder)?;
+        }
+        std::fs::rename(&temp_folder, folder).map_err(|e| format!("Failed to add {full_name} to the store: {e}"))
+    });
+
+    if extracted.is_err() {
+        let _ = utils::remove_path(&temp_folder);
+    }
+    extracted
+}
+
+/// Copies the external files list at `from` to `to`, with paths under `from_folder` moved to `to_folder`
+fn rebase_external_files(from: &str, to: &str, from_folder: &str, to_folder: &str) -> Result<(), String> {
+    let mut buf = String::new();
+    File::open(from)
+        .and_then(|mut f| f.read_to_string(&mut buf))
+        .map_err(|e| format!("Failed to read {from}: {e}"))?;
+
+    let buf = buf.replace(&from_folder.replace('\\', "/"), &to_folder.replace('\\', "/"));
+    OpenOptions::new()
+        .create(true)
+        .truncate(true)
+        .write(true)
+        .open(to)
+        .and_then(|mut f| f.write_all(buf.as_bytes()))
+        .map_err(|e| format!("Failed to write {to}: {e}"))
+}
+
+fn collect_files(folder: &str, relative: &str, files: &mut Vec<String>) -> std::io::Result<()> {
+    for entry in std::fs::read_dir(folder)? {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().to_string();
+        let relative_path = if relative.is_empty() { name } else { format!("{relative}\\{name}") };
+
+        if entry.file_type()?.is_dir() {
+            collect_files(&entry.path().display().to_string(), &relative_path, files)?;
+        } else {
+            files.push(relative_path);
+        }
+    }
+    Ok(())
+}
+
+fn is_user_config(relative_path: &str) -> bool {
+    relative_path.to_lowercase().starts_with("bepinex\\config\\")
+}
+
+fn is_config_file(relative_path: &str) -> bool {
+    let extension = Path::new(relative_path).extension().map(|e| e.to_string_lossy().to_lowercase());
+    is_user_config(relative_path)
+        || extension.map_or(false, |e| CONFIG_EXTENSIONS.contains(&e.as_str()))
+}
+
+/// Whether installing a mod must leave the file at `path` of a profile alone. Files in
+/// `BepInEx\config` are the user's to edit, a mod only provides them if they aren't there yet.
+pub fn keeps_user_config(profile_folder: &str, path: &str) -> bool {
+    path.strip_prefix(profile_folder)
+        .map_or(false, |p| is_user_config(p.trim_start_matches('\\')))
+        && Path::new(path).exists()
+}
+
+/// Links the files of a version in the store into a profile, copying config files and
+/// anything that can't be hardlinked. Everything replaced is saved in `snapshot` first.
+pub fn link_version(store_folder: &str, profile_folder: &str, mod_name: &str, snapshot: &mut Snapshot) -> Result<(), String> {
+    let mut files = vec![];
+    collect_files(store_folder, "", &mut files).map_err(|e| format!("Failed to read {store_folder}: {e}"))?;
+
+    let mod_folder = format!("{profile_folder}\\BepInEx\\plugins\\{mod_name}");
+    if mod_name != "BepInEx-BepInExPack" {
+        snapshot.save(&mod_folder)?;
+        std::fs::create_dir_all(&mod_folder).map_err(|e| format!("Failed to create {mod_folder}: {e}"))?;
+    }
+
+    let external_files = format!("{mod_folder}\\external_files.json");
+    for relative_path in files {
+        let source = format!("{store_folder}\\{relative_path}");
+        let target = format!("{profile_folder}\\{relative_path}");
+        if target == external_files {
+            continue; // Points into the store, written below
+        }
+        if keeps_user_config(profile_folder, &target) {
+            continue;
+        }
+        if !target.starts_with(&mod_folder) || mod_name == "BepInEx-BepInExPack" {
+            snapshot.save(&target)?;
+        }
+
+        if let Some(parent) = Path::new(&target).parent() {
+            std::fs::create_dir_all(parent).map_err(|e| format!("Failed to create {}: {e}", parent.display()))?;
+        }
+        // Writing through an existing link would change every profile that has it
+        utils::remove_path(&target).map_err(|e| format!("Failed to replace {target}: {e}"))?;
+
+        let linked = !is_config_file(&relative_path) && std::fs::hard_link(&source, &target).is_ok();
+        if !linked {
+            std::fs::copy(&source, &target).map_err(|e| format!("Failed to copy {target}: {e}"))?;
+        }
+    }
+
+    // External files are recorded with full paths, make them point to the profile
+    let store_external_files = format!("{store_folder}\\BepInEx\\plugins\\{mod_name}\\external_files.json");
+    if Path::new(&store_external_files).exists() {
+        rebase_external_files(&store_external_files, &external_files, store_folder, profile_folder)?;
+    }
+
+    Ok(())
+}
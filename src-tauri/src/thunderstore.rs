@@ -1,20 +1,31 @@
-use std::collections::HashMap;
+use std::{collections::HashMap, fs::{File, OpenOptions}, io::{Read, Write}, sync::{atomic::{AtomicU64, Ordering}, Arc}};
 
 use chrono::{DateTime, Utc};
 use once_cell::sync::Lazy;
-use regex::Regex;
 use serde::{Deserialize, Serialize};
-use tokio::sync::{Mutex, MutexGuard};
+use tokio::sync::Mutex;
 
-pub static PACKAGE: Lazy<Mutex<Package>> = Lazy::new(|| {
-    Mutex::new(Package {
+use crate::{offline, query::Query, search::{self, SearchIndex}, sources::{self, IndexResponse}, userdata, version::ModVersion};
+
+/// The loaded package. It's never changed in place, `set_package` swaps in a new one, so readers
+/// only hold the lock while they take their own reference to it.
+pub static PACKAGE: Lazy<Mutex<Arc<Package>>> = Lazy::new(|| {
+    Mutex::new(Arc::new(Package {
         categories: vec![],
         mods: HashMap::new(),
+    }))
+});
+
+pub static PACKAGE_STATUS: Lazy<Mutex<PackageStatus>> = Lazy::new(|| {
+    Mutex::new(PackageStatus {
+        source: IndexSource::None,
+        fetched_at: None,
+        reachable: None,
     })
 });
 
-static BUSY: Lazy<Mutex<bool>> = Lazy::new(|| Mutex::new(false));
-static INTERRUPT: Lazy<Mutex<bool>> = Lazy::new(|| Mutex::new(false));
+/// Bumped by every search, a search whose generation isn't the current one has been superseded
+static SEARCH_GENERATION: AtomicU64 = AtomicU64::new(0);
 
 #[derive(Serialize, Deserialize, Clone)]
 pub struct Package {
@@ -26,7 +37,38 @@ pub struct Package {
 pub struct SearchResults {
     pub categories: Vec<String>,
     pub mods: Vec<Mod>,
+    /// Why each mod of `mods` matched, in the same order
+    pub highlights: Vec<Vec<MatchSpan>>,
     pub pages: u32,
+    pub total: usize,
+    pub facets: Facets,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(tag = "kind", content = "message", rename_all = "snake_case")]
+pub enum SearchError {
+    /// A newer search started before this one finished
+    Cancelled,
+    InvalidQuery(String),
+}
+
+/// Result counts per filter. Each filter group is counted as if its own filter wasn't set, so
+/// they tell how many results picking another option would give.
+#[derive(Serialize, Deserialize, Clone, Default)]
+pub struct Facets {
+    pub categories: HashMap<String, usize>,
+    pub mods: usize,
+    pub modpacks: usize,
+    pub deprecated: usize,
+    pub nsfw: usize,
+}
+
+/// Matched part of a mod field, in characters
+#[derive(Serialize, Deserialize, Clone)]
+pub struct MatchSpan {
+    pub field: String,
+    pub start: usize,
+    pub end: usize,
 }
 
 #[derive(Serialize, Deserialize, Clone)]
@@ -40,6 +82,22 @@ pub struct ModInfo {
     pub folder: String,
     pub icon: Option<String>,
     pub enabled: bool,
+    #[serde(default)]
+    pub install_reason: InstallReason,
+    /// Held at its installed version, updates and dependencies can't change it
+    #[serde(default)]
+    pub pinned: bool,
+}
+
+/// Why a mod is in a profile
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum InstallReason {
+    /// The user installed it
+    #[default]
+    Requested,
+    /// It came along as a dependency of another mod
+    Dependency,
 }
 
 #[derive(Serialize, Deserialize, Clone)]
@@ -57,6 +115,8 @@ pub struct Mod {
     pub rating_score: i32,
     pub uuid4: String,
     pub versions: Vec<Version>,
+    #[serde(default)]
+    pub origin: Origin,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
@@ -74,6 +134,44 @@ pub struct Version {
     pub uuid4: String,
     pub version_number: String,
     pub website_url: String,
+    #[serde(default)]
+    pub readme: Option<String>,
+    #[serde(default)]
+    pub origin: Origin,
+}
+
+/// Where a package entry comes from
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum Origin {
+    /// The configured package source, Thunderstore by default
+    #[default]
+    Public,
+    /// The team's private repository
+    Private,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub enum IndexSource {
+    None,
+    Cache,
+    Network,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct PackageStatus {
+    pub source: IndexSource,
+    pub fetched_at: Option<DateTime<Utc>>,
+    /// Result of the last attempt to reach Thunderstore, `None` if it was never contacted
+    pub reachable: Option<bool>,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+pub struct PackageCacheInfo {
+    pub source: Option<String>,
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
+    pub fetched_at: Option<DateTime<Utc>>,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -84,20 +182,28 @@ pub struct Types {
     pub modpacks: i8,
 }
 
-#[tauri::command]
-pub async fn load_package() {
-    let res = reqwest::get("https://thunderstore.io/c/lethal-company/api/v1/package/")
-        .await
-        .unwrap();
-    let data = res.text().await.unwrap();
+fn get_package_cache_paths() -> (String, String) {
+    let cache_dir = userdata::get_cache_dir();
+    (
+        format!("{cache_dir}\\package.json"),
+        format!("{cache_dir}\\package_info.json"),
+    )
+}
+
+/// Sorts versions newest first, like Thunderstore lists them
+pub fn sort_versions(versions: &mut [Version]) {
+    versions.sort_by_cached_key(|v| std::cmp::Reverse(ModVersion::parse(&v.version_number)));
+}
 
+fn parse_package(data: &str) -> Result<Package, String> {
     let mut mods: HashMap<String, Mod> = HashMap::new();
-    let mut mods_v: Vec<Mod> = serde_json::from_str(&data).unwrap();
+    let mut mods_v: Vec<Mod> = serde_json::from_str(data).map_err(|e| e.to_string())?;
     let mut categories: Vec<String> = vec![];
 
-    mods_v.remove(0); // Remove r2modman
+    mods_v.retain(|m| m.full_name != "ebkr-r2modman"); // Remove r2modman
 
-    for m in mods_v {
+    for mut m in mods_v {
+        sort_versions(&mut m.versions);
         for c in &m.categories {
             if c == "Mods" || c == "Modpacks" {
                 continue;
@@ -111,40 +217,218 @@ pub async fn load_package() {
 
     categories.sort();
 
-    *PACKAGE.lock().await = Package { categories, mods }
+    Ok(Package { categories, mods })
+}
+
+/// Caches written before sources existed always came from Thunderstore
+fn is_same_source(info: &PackageCacheInfo, source_id: &str) -> bool {
+    info.source.as_deref().unwrap_or("thunderstore") == source_id
+}
+
+/// Merges the private repository into `package` and makes it the loaded package
+async fn set_package(mut package: Package) {
+    for m in sources::get_private_mods().await {
+        if package.mods.contains_key(&m.full_name) {
+            println!("Private mod {} replaces the public package", m.full_name);
+        }
+        for c in &m.categories {
+            if c != "Mods" && c != "Modpacks" && !package.categories.contains(c) {
+                package.categories.push(c.clone());
+            }
+        }
+        package.mods.insert(m.full_name.clone(), m);
+    }
+
+    package.categories.sort();
+
+    let index = SearchIndex::build(&package);
+    *PACKAGE.lock().await = Arc::new(package);
+    *search::INDEX.write().await = index;
+}
+
+fn read_cached_package(source_id: &str) -> Option<Package> {
+    let (package_file, _) = get_package_cache_paths();
+
+    if !is_same_source(&read_package_cache_info(), source_id) {
+        return None;
+    }
+
+    let mut data = String::new();
+    File::open(&package_file).ok()?.read_to_string(&mut data).ok()?;
+
+    match parse_package(&data) {
+        Ok(package) => Some(package),
+        Err(e) => {
+            println!("Discarding corrupted package cache: {e}");
+            None
+        }
+    }
+}
+
+fn read_package_cache_info() -> PackageCacheInfo {
+    let (_, info_file) = get_package_cache_paths();
+
+    let mut data = String::new();
+    if let Ok(mut f) = File::open(&info_file) {
+        if f.read_to_string(&mut data).is_ok() {
+            return serde_json::from_str::<PackageCacheInfo>(&data).unwrap_or_default();
+        }
+    }
+    PackageCacheInfo::default()
+}
+
+fn write_package_cache(data: &str, info: &PackageCacheInfo) -> Result<(), String> {
+    let (package_file, info_file) = get_package_cache_paths();
+
+    // Write to a temporary file first so an interrupted write never replaces a valid cache
+    let temp_file = format!("{package_file}.tmp");
+    OpenOptions::new()
+        .create(true)
+        .truncate(true)
+        .write(true)
+        .open(&temp_file)
+        .and_then(|mut f| f.write_all(data.as_bytes()))
+        .map_err(|e| e.to_string())?;
+    std::fs::rename(&temp_file, &package_file).map_err(|e| e.to_string())?;
+
+    let buf = serde_json::to_string(info).unwrap();
+    OpenOptions::new()
+        .create(true)
+        .truncate(true)
+        .write(true)
+        .open(&info_file)
+        .and_then(|mut f| f.write_all(buf.as_bytes()))
+        .map_err(|e| e.to_string())
+}
+
+/// Revalidates the package index against the package source. `has_cache` must only be set when
+/// the cached index has been loaded, otherwise a `304 Not Modified` would leave us without mods.
+/// Returns whether the index changed.
+async fn refresh_package(has_cache: bool) -> Result<bool, String> {
+    let source_id = sources::get_source_config().await.id();
+    let source = sources::get_source().await;
+    let info = read_package_cache_info();
+
+    // Validators from another source mean nothing to this one
+    let cache = if has_cache && is_same_source(&info, &source_id) {
+        Some(&info)
+    } else {
+        None
+    };
+
+    let (data, etag, last_modified) = match source.fetch_index(cache).await {
+        Ok(IndexResponse::NotModified) => {
+            let mut status = PACKAGE_STATUS.lock().await;
+            status.source = IndexSource::Network;
+            status.reachable = Some(true);
+            return Ok(false);
+        }
+        Ok(IndexResponse::Updated { data, etag, last_modified }) => (data, etag, last_modified),
+        Err(e) => {
+            PACKAGE_STATUS.lock().await.reachable = Some(false);
+            return Err(e);
+        }
+    };
+
+    let new_info = PackageCacheInfo {
+        source: Some(source_id),
+        etag,
+        last_modified,
+        fetched_at: Some(Utc::now()),
+    };
+    let package = parse_package(&data)?;
+
+    if let Err(e) = write_package_cache(&data, &new_info) {
+        println!("Failed to store package cache: {e}");
+    }
+
+    set_package(package).await;
+    *PACKAGE_STATUS.lock().await = PackageStatus {
+        source: IndexSource::Network,
+        fetched_at: new_info.fetched_at,
+        reachable: Some(true),
+    };
+    Ok(true)
+}
+
+/// Refreshes the package index in the background, keeping the current one if it fails.
+pub fn revalidate_package() {
+    tauri::async_runtime::spawn(async {
+        let has_cache = PACKAGE_STATUS.lock().await.source != IndexSource::None;
+        if let Err(e) = refresh_package(has_cache).await {
+            println!("Failed to refresh package, using cached copy: {e}");
+        }
+    });
+}
+
+/// Loads the index of the configured package source, from its cache if there's one. Fails if
+/// there's neither a cache nor a way to fetch it, leaving only private mods listed.
+#[tauri::command]
+pub async fn load_package() -> Result<(), String> {
+    let source_id = sources::get_source_config().await.id();
+    let offline = offline::is_offline().await && sources::get_source().await.is_remote();
+
+    if let Some(package) = read_cached_package(&source_id) {
+        set_package(package).await;
+        {
+            let mut status = PACKAGE_STATUS.lock().await;
+            status.source = IndexSource::Cache;
+            status.fetched_at = read_package_cache_info().fetched_at;
+        }
+
+        // The cached index is good enough to start with, revalidate it in the background
+        if !offline {
+            revalidate_package();
+        }
+    } else if offline {
+        println!("No cached package available in offline mode");
+        clear_package().await;
+        return Err(String::from("No cached package available in offline mode"));
+    } else if let Err(e) = refresh_package(false).await {
+        println!("Failed to load package: {e}");
+
+        // Whatever is loaded belongs to another source. Private mods are still worth listing.
+        clear_package().await;
+        return Err(format!("Failed to load package: {e}"));
+    }
+    Ok(())
+}
+
+async fn clear_package() {
+    set_package(Package { categories: vec![], mods: HashMap::new() }).await;
+    let mut status = PACKAGE_STATUS.lock().await;
+    status.source = IndexSource::None;
+    status.fetched_at = None;
 }
 
-pub async fn get_package<'a>() -> MutexGuard<'a, Package> {
-    return PACKAGE.lock().await;
+/// The package as it's loaded now. It stays the same while it's used, even if another is loaded.
+pub async fn get_package() -> Arc<Package> {
+    PACKAGE.lock().await.clone()
 }
 
-pub fn parse_mod_version(version_name: &str) -> (String, String) {
-    let mut v_split: Vec<&str> = version_name.split('-').collect();
-    let version_number = v_split.pop().unwrap().to_owned();
-    let full_name = v_split.join("-");
-    (full_name, version_number)
+/// Splits a version name like `Owner-Name-1.0.0` into the mod's full name and its version
+pub fn parse_mod_version(version_name: &str) -> Option<(String, ModVersion)> {
+    let (full_name, version_number) = version_name.rsplit_once('-')?;
+    Some((full_name.to_owned(), ModVersion::parse(version_number)?))
 }
 
-pub async fn get_mod(full_name: &str) -> Mod {
+/// Looks up a mod in the index loaded from the package source
+pub async fn get_mod(full_name: &str) -> Option<Mod> {
     let package = get_package().await;
-    package.mods.get(full_name).unwrap().clone()
+    package.mods.get(full_name).cloned()
 }
 
 pub async fn get_mod_version(version_name: &str) -> Option<Version> {
-    let (full_name, version_number) = parse_mod_version(version_name);
+    let (full_name, version_number) = parse_mod_version(version_name)?;
     let package = get_package().await;
 
-    match package.mods.get(&full_name) {
-        Some(t_mod) => Some(
-            t_mod
-                .versions
-                .iter()
-                .find(|v| v.version_number == version_number)
-                .unwrap()
-                .clone(),
-        ),
-        None => None,
-    }
+    package.mods.get(&full_name).and_then(|t_mod| {
+        t_mod
+            .versions
+            .iter()
+            .find(|v| ModVersion::parse(&v.version_number).as_ref() == Some(&version_number))
+            .cloned()
+    })
 }
 
 #[tauri::command]
@@ -154,42 +438,43 @@ pub async fn search(
     sort: String,
     types: Types,
     categories: Vec<String>,
-) -> Result<SearchResults, ()> {
-    if *BUSY.lock().await {
-        *INTERRUPT.lock().await = true; // Stop searching, client started a new search
-    }
+) -> Result<SearchResults, SearchError> {
+    let generation = SEARCH_GENERATION.fetch_add(1, Ordering::SeqCst) + 1;
+    let is_stale = || SEARCH_GENERATION.load(Ordering::SeqCst) != generation;
 
-    *BUSY.lock().await = true;
+    let query = Query::parse(&query).map_err(SearchError::InvalidQuery)?;
 
-    let mut mods: Vec<&Mod> = vec![];
-    // let mut fuzzy_mods: Vec<&Mod> = vec![];
+    let index = search::INDEX.read().await;
     let package = get_package().await;
-    let re = Regex::new(&format!("(?i){}", &query)).unwrap();
+    if is_stale() {
+        return Err(SearchError::Cancelled);
+    }
+
+    let mut mods: Vec<(&Mod, f32)> = vec![];
+    let mut facets = Facets::default();
 
-    for (_, m) in &package.mods {
+    for (full_name, score) in index.search(&query.text) {
+        let Some(m) = package.mods.get(full_name) else {
+            continue;
+        };
         if m.full_name == "BepInEx-BepInExPack" {
             continue;
         }
 
-        let content = format!("{} {} {}", m.full_name, m.name, m.versions[0].description);
         let mut contains_type = (types.mods == 0 && (types.modpacks == 0 || types.modpacks == -1))
                                     || (types.mods == -1 && types.modpacks == 0);
-        let mut contains_category = categories.len() == 0;
+        let mut contains_category = categories.is_empty();
 
-        if *INTERRUPT.lock().await {
-            *INTERRUPT.lock().await = false;
-            return Err(());
+        if is_stale() {
+            return Err(SearchError::Cancelled); // Client started a new search
         }
 
         if types.mods == 1 && m.categories.iter().any(|c| c == "Mods") || types.modpacks == 1 && m.categories.iter().any(|c| c == "Modpacks") {
             contains_type = true;
         }
-        if !contains_type { continue }
-
         if types.mods == -1 && m.categories.iter().any(|c| c == "Mods") || types.modpacks == -1 && m.categories.iter().any(|c| c == "Modpacks") {
             contains_type = false;
         }
-        if !contains_type { continue }
 
         for c in &m.categories {
             if categories.contains(c) {
@@ -197,49 +482,79 @@ pub async fn search(
                 break;
             }
         }
-        if !contains_category {
-            continue;
+
+        // Counted before the query filters, so their own filter doesn't hide the other option
+        if contains_type && contains_category {
+            if m.is_deprecated && query.matches_except(m, "deprecated") {
+                facets.deprecated += 1;
+            }
+            if m.has_nsfw_content && query.matches_except(m, "nsfw") {
+                facets.nsfw += 1;
+            }
         }
 
-        // Keyword matching
-        if let Some(_) = re.captures(&content) {
-            mods.push(m);
+        if !query.matches(m) {
             continue;
         }
 
-        // Fuzzing matching (Needs improvements)
-        let words = content.split_whitespace();
-        for word in words {
-            if !word.is_ascii() {
-                continue;
-            }
-            let similarity = textdistance::nstr::sift4_simple(&query, word);
-            if 1.0 - similarity >= 0.80 {
-                mods.push(m);
-                break;
+        if contains_type {
+            for c in &m.categories {
+                if c != "Mods" && c != "Modpacks" {
+                    *facets.categories.entry(c.clone()).or_default() += 1;
+                }
             }
         }
-    }
+        if contains_category {
+            facets.mods += m.categories.iter().any(|c| c == "Mods") as usize;
+            facets.modpacks += m.categories.iter().any(|c| c == "Modpacks") as usize;
+        }
+        if !contains_type || !contains_category {
+            continue;
+        }
 
-    *BUSY.lock().await = false;
+        mods.push((m, score));
+    }
 
+    // Hits come ranked by relevance, other orders keep it between equal mods
     match sort.as_str() {
-        "rating" => mods.sort_by(|a, b| b.rating_score.partial_cmp(&a.rating_score).unwrap()),
-        "updated" => mods.sort_by(|a, b| b.date_updated.partial_cmp(&a.date_updated).unwrap()),
-        "created" => mods.sort_by(|a, b| b.date_created.partial_cmp(&a.date_created).unwrap()),
-        "downloads" => mods.sort_by(|a, b| b.versions[0].downloads.partial_cmp(&a.versions[0].downloads).unwrap()),
-        "name" => mods.sort_by(|a, b| a.name.partial_cmp(&b.name).unwrap()),
-        _ => {}
+        "rating" => mods.sort_by(|(a, _), (b, _)| b.rating_score.partial_cmp(&a.rating_score).unwrap()),
+        "updated" => mods.sort_by(|(a, _), (b, _)| b.date_updated.partial_cmp(&a.date_updated).unwrap()),
+        "created" => mods.sort_by(|(a, _), (b, _)| b.date_created.partial_cmp(&a.date_created).unwrap()),
+        "downloads" => mods.sort_by(|(a, _), (b, _)| b.versions[0].downloads.partial_cmp(&a.versions[0].downloads).unwrap()),
+        "name" => mods.sort_by(|(a, _), (b, _)| a.name.partial_cmp(&b.name).unwrap()),
+        // Equally relevant mods, like every mod without a query, go by rating
+        _ => mods.sort_by(|(a, s_a), (b, s_b)| s_b.partial_cmp(s_a).unwrap().then(b.rating_score.cmp(&a.rating_score))),
     }
-    // fuzzy_mods.sort_by(|a, b| b.rating_score.partial_cmp(&a.rating_score).unwrap());
-    // mods.append(&mut fuzzy_mods);
 
     // Only return 20 mods per page
-    let page_mods = mods[page * 20..std::cmp::min(page * 20 + 20, mods.len())].to_vec();
+    let start = std::cmp::min(page * 20, mods.len());
+    let page_mods = mods[start..std::cmp::min(start + 20, mods.len())].to_vec();
+
+    let terms = search::tokenize(&query.text, false);
+    let highlights = page_mods
+        .iter()
+        .map(|(m, _)| {
+            let description = m.versions.first().map_or("", |v| v.description.as_str());
+            let mut spans = vec![];
+            for (field, text) in [("name", m.name.as_str()), ("owner", m.owner.as_str()), ("description", description)] {
+                for (start, end) in search::highlight(text, &terms) {
+                    spans.push(MatchSpan { field: String::from(field), start, end });
+                }
+            }
+            spans
+        })
+        .collect();
+
+    if is_stale() {
+        return Err(SearchError::Cancelled);
+    }
 
     Ok(SearchResults {
         categories: package.categories.clone(),
-        mods: page_mods.iter().map(|m| (*m).clone()).collect(),
-        pages: mods.len() as u32 / 20,
+        mods: page_mods.iter().map(|(m, _)| (*m).clone()).collect(),
+        highlights,
+        pages: ((mods.len() + 19) / 20) as u32,
+        total: mods.len(),
+        facets,
     })
 }
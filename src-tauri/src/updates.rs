@@ -0,0 +1,148 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::{profiles, thunderstore, version::ModVersion};
+
+/// Most significant version component that changed
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum VersionDelta {
+    Major,
+    Minor,
+    Patch,
+    Build,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct ModUpdate {
+    pub full_name: String,
+    pub name: String,
+    pub installed: ModVersion,
+    pub latest: ModVersion,
+    pub delta: VersionDelta,
+    /// When the latest version was released
+    pub released: DateTime<Utc>,
+}
+
+/// An installed version that shouldn't be relied on anymore
+#[derive(Serialize, Deserialize, Clone)]
+pub struct ModWarning {
+    pub full_name: String,
+    pub version_number: String,
+    /// The installed version was removed from the package index
+    pub inactive: bool,
+    /// The whole package was deprecated by its author
+    pub deprecated: bool,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct ProfileUpdates {
+    pub profile: String,
+    pub updates: Vec<ModUpdate>,
+    pub warnings: Vec<ModWarning>,
+}
+
+impl VersionDelta {
+    fn between(from: &ModVersion, to: &ModVersion) -> VersionDelta {
+        if from.major != to.major {
+            VersionDelta::Major
+        } else if from.minor != to.minor {
+            VersionDelta::Minor
+        } else if from.patch != to.patch {
+            VersionDelta::Patch
+        } else {
+            VersionDelta::Build
+        }
+    }
+}
+
+pub async fn check_profile_updates(profile: String) -> ProfileUpdates {
+    let mods = profiles::get_profile_mods(profile.clone()).await;
+    let package = thunderstore::get_package().await;
+
+    let mut result = ProfileUpdates {
+        profile,
+        updates: vec![],
+        warnings: vec![],
+    };
+
+    for installed in mods {
+        let Some(_mod) = package.mods.get(&installed.full_name) else {
+            continue; // Not from the package index, nothing to compare to
+        };
+        let Some(installed_version) = ModVersion::parse(&installed.version_number) else {
+            continue;
+        };
+
+        let inactive = _mod
+            .versions
+            .iter()
+            .find(|v| ModVersion::parse(&v.version_number).as_ref() == Some(&installed_version))
+            .map_or(true, |v| !v.is_active);
+        if inactive || _mod.is_deprecated {
+            result.warnings.push(ModWarning {
+                full_name: installed.full_name.clone(),
+                version_number: installed.version_number.clone(),
+                inactive,
+                deprecated: _mod.is_deprecated,
+            });
+        }
+
+        if installed.pinned {
+            continue; // Held at this version on purpose
+        }
+
+        // Versions are sorted newest first
+        let latest = _mod
+            .versions
+            .iter()
+            .filter(|v| v.is_active)
+            .find_map(|v| Some((v, ModVersion::parse(&v.version_number)?)));
+
+        if let Some((version, latest)) = latest.filter(|(_, l)| *l > installed_version) {
+            result.updates.push(ModUpdate {
+                full_name: installed.full_name,
+                name: installed.name,
+                delta: VersionDelta::between(&installed_version, &latest),
+                installed: installed_version,
+                latest,
+                released: version.date_created,
+            });
+        }
+    }
+
+    result
+}
+
+/// Finds newer versions of the mods installed in every profile, or only in `profile`
+#[tauri::command]
+pub async fn check_updates(profile: Option<String>) -> Vec<ProfileUpdates> {
+    let names = match profile {
+        Some(profile) => vec![profile],
+        None => profiles::get_profiles().await.into_iter().map(|p| p.name).collect(),
+    };
+
+    let mut updates = vec![];
+    for name in names {
+        updates.push(check_profile_updates(name).await);
+    }
+    updates
+}
+
+/// Updates the outdated mods of a profile, only the ones in `mods` if given, through the
+/// resolver. Returns the versions that were installed.
+#[tauri::command]
+pub async fn update_profile(profile: String, mods: Option<Vec<String>>) -> Result<Vec<String>, String> {
+    let version_names: Vec<String> = check_profile_updates(profile.clone())
+        .await
+        .updates
+        .into_iter()
+        .filter(|u| mods.as_ref().map_or(true, |m| m.contains(&u.full_name)))
+        .map(|u| format!("{}-{}", u.full_name, u.latest))
+        .collect();
+
+    if version_names.is_empty() {
+        return Ok(vec![]);
+    }
+    profiles::install_mods(&profile, &version_names, false).await
+}
@@ -1,4 +1,4 @@
-use crate::installs::Install;
+use crate::{installs::Install, sources::SourceConfig};
 use directories::UserDirs;
 use once_cell::sync::Lazy;
 use serde::{Deserialize, Serialize};
@@ -16,7 +16,14 @@ static SETTINGS: Lazy<Arc<Mutex<Settings>>> = Lazy::new(|| {
     Arc::new(Mutex::new(Settings {
         selected_install: None,
         installs: Some(vec![]),
-        game_status: None
+        game_status: None,
+        offline: None,
+        package_source: None,
+        private_repository: None,
+        server_address: None,
+        max_concurrent_downloads: None,
+        archive_cache_limit: None,
+        store_mode: None
     }))
 });
 
@@ -31,7 +38,14 @@ pub struct GameStatus {
 pub struct Settings {
     pub selected_install: Option<String>,
     pub installs: Option<Vec<Install>>,
-    pub game_status: Option<GameStatus>
+    pub game_status: Option<GameStatus>,
+    pub offline: Option<bool>,
+    pub package_source: Option<SourceConfig>,
+    pub private_repository: Option<String>,
+    pub server_address: Option<String>,
+    pub max_concurrent_downloads: Option<usize>,
+    pub archive_cache_limit: Option<u64>,
+    pub store_mode: Option<bool>
 }
 
 pub fn get_app_dir() -> String {
@@ -40,9 +54,14 @@ pub fn get_app_dir() -> String {
     app_dir
 }
 
+pub fn get_cache_dir() -> String {
+    format!("{}\\{}", get_app_dir(), "cache")
+}
+
 pub async fn setup() {
     let app_dir = get_app_dir();
     let profiles_path = format!("{}\\{}", &app_dir, "profiles");
+    let cache_path = get_cache_dir();
     let config_file = format!("{}\\{}", &app_dir, "config.json");
 
     // Create directories
@@ -52,6 +71,10 @@ pub async fn setup() {
     if !Path::new(&profiles_path).exists() {
         create_dir(&profiles_path).unwrap();
     }
+    if !Path::new(&cache_path).exists() {
+        create_dir(&cache_path).unwrap();
+    }
+    std::fs::create_dir_all(format!("{}\\archives\\downloads", &cache_path)).unwrap();
 
     if !Path::new(&config_file).exists() {
         save_data().await;
@@ -35,20 +35,52 @@ pub fn show_in_explorer(path: String) {
     println!("Created symlink from {} -> {}", symlink, destination);
 } */
 
-pub fn extract_file(file: &mut ZipFile, outpath: &str) {
+pub fn extract_file(file: &mut ZipFile, outpath: &str) -> std::io::Result<()> {
     // println!("Extracting file {outpath}");
 
     if let Some(p) = Path::new(&outpath).parent() {
         if !p.exists() {
-            std::fs::create_dir_all(p).unwrap();
+            std::fs::create_dir_all(p)?;
         }
     }
 
-    let mut outfile = std::fs::File::create(&outpath).unwrap();
-    std::io::copy(file, &mut outfile).unwrap();
+    // Replace the file rather than writing into it, it may be linked to the store
+    remove_path(outpath)?;
+    let mut outfile = std::fs::File::create(outpath)?;
+    std::io::copy(file, &mut outfile)?;
+    Ok(())
+}
+
+pub fn copy_dir(from: &str, to: &str) -> std::io::Result<()> {
+    std::fs::create_dir_all(to)?;
+
+    for entry in std::fs::read_dir(from)? {
+        let entry = entry?;
+        let target = format!("{}\\{}", to, entry.file_name().to_string_lossy());
+
+        if entry.file_type()?.is_dir() {
+            copy_dir(&entry.path().display().to_string(), &target)?;
+        } else {
+            std::fs::copy(entry.path(), &target)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Removes a file or a folder with everything in it
+pub fn remove_path(path: &str) -> std::io::Result<()> {
+    let path = Path::new(path);
+    if path.is_dir() {
+        std::fs::remove_dir_all(path)
+    } else if path.exists() {
+        std::fs::remove_file(path)
+    } else {
+        Ok(())
+    }
 }
 
 pub fn str_skip_to<'a>(str: &'a str, to: &str) -> &'a str {
     let i = str.find(to).unwrap_or(0);
-    return &str[i..];
+    &str[i..]
 }
@@ -0,0 +1,140 @@
+use std::{fmt, str::FromStr};
+
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+
+/// A package version like `1.2.3`. Thunderstore requires Major.Minor.Patch, but manifests and
+/// dependency strings also carry versions with fewer or more components, like `1.0` or `1.0.0.2`.
+#[derive(Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
+pub struct ModVersion {
+    pub major: u64,
+    pub minor: u64,
+    pub patch: u64,
+    /// Components past the patch number, without trailing zeros so `1.0.0.0` equals `1.0.0`
+    pub extra: Vec<u64>,
+}
+
+impl ModVersion {
+    pub fn parse(version: &str) -> Option<ModVersion> {
+        let version = version.trim();
+        let version = version.strip_prefix(['v', 'V']).unwrap_or(version);
+
+        let mut components = version
+            .split('.')
+            .map(|c| c.parse::<u64>().ok())
+            .collect::<Option<Vec<u64>>>()?;
+
+        while components.len() > 3 && components.last() == Some(&0) {
+            components.pop();
+        }
+
+        let get = |i: usize| components.get(i).copied().unwrap_or(0);
+        Some(ModVersion {
+            major: get(0),
+            minor: get(1),
+            patch: get(2),
+            extra: components.iter().skip(3).copied().collect(),
+        })
+    }
+
+    pub fn same_major(&self, other: &ModVersion) -> bool {
+        self.major == other.major
+    }
+
+    /// Whether this version can stand in for `required`, a newer version without breaking changes
+    pub fn satisfies(&self, required: &ModVersion) -> bool {
+        self.same_major(required) && self >= required
+    }
+}
+
+impl fmt::Display for ModVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}.{}", self.major, self.minor, self.patch)?;
+        for c in &self.extra {
+            write!(f, ".{c}")?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for ModVersion {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        ModVersion::parse(s).ok_or_else(|| format!("Invalid version \"{s}\""))
+    }
+}
+
+impl Serialize for ModVersion {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+
+impl<'de> Deserialize<'de> for ModVersion {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer)?.parse().map_err(de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::thunderstore::parse_mod_version;
+
+    fn v(version: &str) -> ModVersion {
+        ModVersion::parse(version).unwrap()
+    }
+
+    #[test]
+    fn parses_short_and_long_versions() {
+        assert_eq!(v("1.0"), ModVersion { major: 1, minor: 0, patch: 0, extra: vec![] });
+        assert_eq!(v("1.0.0.2"), ModVersion { major: 1, minor: 0, patch: 0, extra: vec![2] });
+        assert_eq!(v("v2.1.3"), ModVersion { major: 2, minor: 1, patch: 3, extra: vec![] });
+        assert_eq!(v(" 5 ").to_string(), "5.0.0");
+    }
+
+    #[test]
+    fn rejects_invalid_versions() {
+        for version in ["", "v", "1..0", "1.0-beta", "one.two", "1.0.", "-1.0.0"] {
+            assert_eq!(ModVersion::parse(version), None, "{version}");
+        }
+    }
+
+    #[test]
+    fn orders_numerically() {
+        assert!(v("1.10.0") > v("1.9.0"));
+        assert!(v("2.0.0") > v("1.99.99"));
+        assert!(v("1.0.0.1") > v("1.0.0"));
+        assert_eq!(v("1.0"), v("1.0.0"));
+        assert_eq!(v("1.0.0.0"), v("1.0.0"));
+    }
+
+    #[test]
+    fn satisfies_newer_versions_of_the_same_major() {
+        assert!(v("1.2.0").satisfies(&v("1.1.5")));
+        assert!(v("1.1.5").satisfies(&v("1.1.5")));
+        assert!(!v("1.1.4").satisfies(&v("1.1.5")));
+        assert!(!v("2.0.0").satisfies(&v("1.1.5")));
+
+        assert!(v("1.0.0").same_major(&v("1.9.9")));
+        assert!(!v("1.0.0").same_major(&v("0.9.0")));
+    }
+
+    #[test]
+    fn splits_version_names_with_hyphens() {
+        assert_eq!(
+            parse_mod_version("Evaisa-LethalLib-0.14.2"),
+            Some((String::from("Evaisa-LethalLib"), v("0.14.2")))
+        );
+        assert_eq!(
+            parse_mod_version("x753-More_Suits-1.4.1"),
+            Some((String::from("x753-More_Suits"), v("1.4.1")))
+        );
+        assert_eq!(
+            parse_mod_version("Some-Team-Mod-With-Hyphens-2.0"),
+            Some((String::from("Some-Team-Mod-With-Hyphens"), v("2.0")))
+        );
+        assert_eq!(parse_mod_version("NoVersion"), None);
+        assert_eq!(parse_mod_version("Owner-Name-latest"), None);
+    }
+}